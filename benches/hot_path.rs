@@ -0,0 +1,86 @@
+//! The per-flow hot path over `fixtures/flows.jsonl`, 2000 flows from a mix of exporters,
+//! address families and protocols: parsing, the private address check and building points.
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+
+use GoFlow2Influxdb::{
+    Config, FlowData,
+    cardinality::{CardinalityLimiter, OverflowAction},
+    default_private_ranges,
+    enrich::Enrichment,
+    flow_to_datapoint, is_private_ip,
+};
+
+const FLOWS: &str = include_str!("fixtures/flows.jsonl");
+
+fn flows() -> Vec<FlowData> {
+    FLOWS
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+fn parse(c: &mut Criterion) {
+    let lines: Vec<&str> = FLOWS.lines().collect();
+    let mut group = c.benchmark_group("hot_path");
+    group.throughput(Throughput::Elements(lines.len() as u64));
+    group.bench_function("from_str", |b| {
+        b.iter(|| {
+            for line in &lines {
+                black_box(serde_json::from_str::<FlowData>(black_box(line)).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn private_ip(c: &mut Criterion) {
+    let flows = flows();
+    let ranges = default_private_ranges();
+    let mut group = c.benchmark_group("hot_path");
+    group.throughput(Throughput::Elements(flows.len() as u64 * 2));
+    group.bench_function("is_private_ip", |b| {
+        b.iter(|| {
+            for flow in &flows {
+                black_box(is_private_ip(black_box(&flow.src_addr), &ranges));
+                black_box(is_private_ip(black_box(&flow.dst_addr), &ranges));
+            }
+        })
+    });
+    group.finish();
+}
+
+fn datapoint(c: &mut Criterion) {
+    let flows = flows();
+    let config = Config::from_lookup(|key| match key {
+        "INFLUXDB_URL" => Some("http://localhost:8086".to_string()),
+        "INFLUXDB_TOKEN" | "INFLUXDB_ORG" | "INFLUXDB_BUCKET" => Some("bench".to_string()),
+        _ => None,
+    })
+    .unwrap();
+    let enrichment = Enrichment::from_config(&config).unwrap();
+    let tags = CardinalityLimiter::new(0, OverflowAction::Field);
+    let now = flows
+        .iter()
+        .map(|flow| flow.time_received_ns)
+        .max()
+        .unwrap();
+
+    let mut group = c.benchmark_group("hot_path");
+    group.throughput(Throughput::Elements(flows.len() as u64));
+    group.bench_function("flow_to_datapoint", |b| {
+        b.iter(|| {
+            for flow in &flows {
+                black_box(
+                    flow_to_datapoint(black_box(flow), "netflow", &config, &enrichment, &tags, now)
+                        .unwrap(),
+                );
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parse, private_ip, datapoint);
+criterion_main!(benches);
@@ -0,0 +1,24 @@
+//! JSON record parsing. `decode_json` is the serde_json path by default and the simd-json
+//! one with `cargo bench --features simd-json`; `serde_json` is the baseline either way.
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+
+use GoFlow2Influxdb::{FlowData, decode_json};
+
+const RECORD: &[u8] = br#"{"type":"IPFIX","time_received_ns":1755046784453621258,"sequence_num":96,"sampling_rate":1000,"sampler_address":"192.0.2.1","time_flow_start_ns":1755046784000000000,"time_flow_end_ns":1755046784400000000,"bytes":15230,"packets":12,"src_addr":"192.168.1.10","dst_addr":"198.51.100.20","etype":"IPv4","proto":"TCP","src_port":51234,"dst_port":443,"in_if":1,"out_if":2,"src_mac":"00:11:22:33:44:55","dst_mac":"66:77:88:99:aa:bb","src_vlan":10,"dst_vlan":20,"ip_tos":184,"forwarding_status":64,"ip_ttl":64,"ip_flags":2,"tcp_flags":24,"src_as":64512,"dst_as":15169,"next_hop":"192.0.2.254","as_path":[64512,3356,15169],"observation_domain_id":1}"#;
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Bytes(RECORD.len() as u64));
+    group.bench_function("serde_json", |b| {
+        b.iter(|| serde_json::from_slice::<FlowData>(black_box(RECORD)).unwrap())
+    });
+    group.bench_function("decode_json", |b| {
+        b.iter(|| decode_json(black_box(RECORD)).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -0,0 +1,52 @@
+//! Merging of flows that share a 5-tuple within one flush window (`AGGREGATE_5TUPLE`).
+
+use std::collections::HashMap;
+
+use crate::FlowData;
+
+/// (src_addr, dst_addr, src_port, dst_port, proto)
+type FlowKey = (String, String, u16, u16, String);
+
+#[derive(Default)]
+pub struct Aggregator {
+    flows: HashMap<FlowKey, FlowData>,
+}
+
+impl Aggregator {
+    /// Adds `flow` to the flow with the same 5-tuple: bytes, packets and dropped flows are
+    /// summed and the window widened to the earliest start and latest end. The point
+    /// timestamp (`time_received_ns`) is the earliest one seen; everything else comes from
+    /// the first flow added.
+    pub fn add(&mut self, flow: FlowData) {
+        let key = (
+            flow.src_addr.clone(),
+            flow.dst_addr.clone(),
+            flow.src_port,
+            flow.dst_port,
+            flow.proto.clone(),
+        );
+
+        let Some(existing) = self.flows.get_mut(&key) else {
+            self.flows.insert(key, flow);
+            return;
+        };
+
+        existing.bytes = existing.bytes.saturating_add(flow.bytes);
+        existing.packets = existing.packets.saturating_add(flow.packets);
+        existing.dropped_flows = existing.dropped_flows.saturating_add(flow.dropped_flows);
+        existing.time_flow_start_ns = existing.time_flow_start_ns.min(flow.time_flow_start_ns);
+        existing.time_flow_end_ns = existing.time_flow_end_ns.max(flow.time_flow_end_ns);
+        if flow.time_received_ns < existing.time_received_ns {
+            existing.time_received_ns = flow.time_received_ns;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.flows.len()
+    }
+
+    /// Empties the window, returning one merged flow per 5-tuple.
+    pub fn drain(&mut self) -> impl Iterator<Item = FlowData> + '_ {
+        self.flows.drain().map(|(_, flow)| flow)
+    }
+}
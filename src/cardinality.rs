@@ -0,0 +1,96 @@
+//! Guards InfluxDB's series index against runaway tag cardinality, e.g. during scans.
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::Mutex,
+};
+use tracing::warn;
+
+use crate::{
+    error::{FlowError, Result},
+    point::PointBuilder,
+};
+
+/// What happens to a new value of a tag key that has reached `MAX_TAG_CARDINALITY`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverflowAction {
+    /// Keep the value, written as a field of the same name instead of a tag
+    Field,
+    Drop,
+}
+
+impl FromStr for OverflowAction {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "field" => Ok(OverflowAction::Field),
+            "drop" => Ok(OverflowAction::Drop),
+            other => Err(FlowError::Config(format!(
+                "Invalid MAX_TAG_CARDINALITY_ACTION '{}', expected one of: field, drop",
+                other
+            ))),
+        }
+    }
+}
+
+/// Adds tags while each key has at most `limit` distinct values (0 disables the limit).
+///
+/// Values seen before the limit was reached stay tags, so existing series keep growing;
+/// only values that would create new series are demoted.
+pub struct CardinalityLimiter {
+    limit: usize,
+    action: OverflowAction,
+    seen: Mutex<HashMap<String, HashSet<String>>>,
+    /// Keys whose overflow has already been logged
+    warned: Mutex<HashSet<String>>,
+}
+
+impl CardinalityLimiter {
+    pub fn new(limit: usize, action: OverflowAction) -> Self {
+        CardinalityLimiter {
+            limit,
+            action,
+            seen: Mutex::new(HashMap::new()),
+            warned: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn tag(
+        &self,
+        builder: PointBuilder,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> PointBuilder {
+        let (key, value) = (key.into(), value.into());
+        if self.limit == 0 || self.admit(&key, &value) {
+            return builder.tag(key, value);
+        }
+
+        match self.action {
+            OverflowAction::Field => builder.field(key, value),
+            OverflowAction::Drop => builder,
+        }
+    }
+
+    fn admit(&self, key: &str, value: &str) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        let values = seen.entry(key.to_string()).or_default();
+        if values.contains(value) {
+            return true;
+        }
+        if values.len() < self.limit {
+            values.insert(value.to_string());
+            return true;
+        }
+
+        if self.warned.lock().unwrap().insert(key.to_string()) {
+            warn!(
+                "Tag {} reached MAX_TAG_CARDINALITY ({}); new values are no longer written as tags",
+                key, self.limit
+            );
+        }
+        false
+    }
+}
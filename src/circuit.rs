@@ -0,0 +1,147 @@
+//! Circuit breaker around InfluxDB writes.
+//!
+//! After `threshold` consecutive failed batches (each already retried) the circuit opens and
+//! batches are rejected without touching InfluxDB until `cooldown` has passed. The next batch
+//! is then a probe: success closes the circuit, failure opens it for another cooldown.
+
+use influxdb2::models::WriteDataPoint;
+use std::{
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+use tracing::{info, warn};
+
+use crate::{
+    Config,
+    error::{FlowError, Result},
+    point::Point,
+};
+
+/// What happens to batches rejected while the circuit is open.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpenPolicy {
+    Drop,
+    /// Append the batch as line protocol to `CIRCUIT_BREAKER_BUFFER_FILE`
+    Buffer,
+}
+
+impl FromStr for OpenPolicy {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "drop" => Ok(OpenPolicy::Drop),
+            "buffer" => Ok(OpenPolicy::Buffer),
+            other => Err(FlowError::Config(format!(
+                "Invalid CIRCUIT_BREAKER_POLICY '{}', expected one of: drop, buffer",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A probe batch is being written
+    HalfOpen,
+}
+
+pub struct CircuitBreaker {
+    /// Consecutive failures that open the circuit; 0 disables the breaker
+    threshold: u32,
+    cooldown: Duration,
+    policy: OpenPolicy,
+    buffer_file: Option<String>,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    pub fn from_config(config: &Config) -> Self {
+        CircuitBreaker {
+            threshold: config.circuit_breaker_threshold,
+            cooldown: Duration::from_millis(config.circuit_breaker_cooldown_ms),
+            policy: config.circuit_breaker_policy,
+            buffer_file: config.circuit_breaker_buffer_file.clone(),
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    /// Whether a batch may be written now. Once the cooldown has passed, the first caller
+    /// gets to write the probe and everyone else is rejected until it finishes.
+    pub fn allow(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Closed { .. } => true,
+            State::Open { until } if Instant::now() >= until => {
+                info!("Circuit breaker half-open, probing InfluxDB");
+                *state = State::HalfOpen;
+                true
+            }
+            State::Open { .. } | State::HalfOpen => false,
+        }
+    }
+
+    /// Records the outcome of a batch write, after its retries.
+    pub fn record(&self, succeeded: bool) {
+        if self.threshold == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        *state = match (*state, succeeded) {
+            (State::HalfOpen, true) => {
+                info!("Circuit breaker closed, InfluxDB writes recovered");
+                State::Closed { failures: 0 }
+            }
+            (_, true) => State::Closed { failures: 0 },
+            (State::Closed { failures }, false) if failures + 1 < self.threshold => State::Closed {
+                failures: failures + 1,
+            },
+            (_, false) => {
+                warn!(
+                    "Circuit breaker open, rejecting batches for {}ms",
+                    self.cooldown.as_millis()
+                );
+                State::Open {
+                    until: Instant::now() + self.cooldown,
+                }
+            }
+        };
+    }
+
+    /// Drops or buffers a batch that `allow` rejected.
+    pub async fn reject(&self, batch: Vec<Point>) -> Result<()> {
+        match (self.policy, &self.buffer_file) {
+            (OpenPolicy::Buffer, Some(path)) => {
+                let mut lines = Vec::new();
+                for point in &batch {
+                    point.write_data_point_to(&mut lines)?;
+                }
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await?;
+                file.write_all(&lines).await?;
+                warn!(
+                    "Circuit breaker open, buffered batch of {} points to {}",
+                    batch.len(),
+                    path
+                );
+            }
+            _ => warn!(
+                "Circuit breaker open, dropped batch of {} points",
+                batch.len()
+            ),
+        }
+        Ok(())
+    }
+}
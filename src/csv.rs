@@ -0,0 +1,142 @@
+//! CSV records (`INPUT_FORMAT=csv`): a header row naming `FlowData` fields, as in the JSON
+//! records, then one flow per row.
+
+use serde::{
+    Deserialize,
+    de::{
+        self, Deserializer, IntoDeserializer, Unexpected, Visitor,
+        value::{Error, MapDeserializer, SeqDeserializer},
+    },
+    forward_to_deserialize_any,
+};
+use std::sync::Arc;
+
+use crate::{
+    FlowData,
+    error::{FlowError, Result},
+};
+
+/// Column names of a CSV stream, shared by every row read from it
+pub type CsvHeader = Arc<[String]>;
+
+pub fn parse_header(line: &str) -> CsvHeader {
+    split_row(line)
+        .into_iter()
+        .map(|name| name.trim().to_string())
+        .collect()
+}
+
+/// Decodes `row` by `header`. Columns missing from the header and empty cells are treated
+/// as fields missing from a JSON record, columns `FlowData` has no field for are ignored.
+pub fn decode_row(header: &[String], row: &[u8]) -> Result<FlowData> {
+    let row = std::str::from_utf8(row).map_err(|e| FlowError::Parse(e.to_string()))?;
+    let cells = split_row(row);
+    if cells.len() != header.len() {
+        return Err(FlowError::Parse(format!(
+            "CSV row has {} cells, the header {} columns",
+            cells.len(),
+            header.len()
+        )));
+    }
+
+    let fields = header
+        .iter()
+        .zip(&cells)
+        .filter(|(_, cell)| !cell.is_empty())
+        .map(|(name, cell)| (name.as_str(), Cell(cell)));
+    FlowData::deserialize(MapDeserializer::<_, Error>::new(fields))
+        .map_err(|e| FlowError::Parse(e.to_string()))
+}
+
+/// Splits a row at commas, with cells optionally quoted and `""` in a quoted cell read as `"`.
+fn split_row(row: &str) -> Vec<String> {
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = row.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                cell.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => cells.push(std::mem::take(&mut cell)),
+            c => cell.push(c),
+        }
+    }
+    cells.push(cell);
+    cells
+}
+
+/// One cell, parsed as whatever type its field has. List fields such as `as_path` are
+/// separated by spaces, semicolons or commas, optionally in brackets (`[64512 3356]`).
+struct Cell<'a>(&'a str);
+
+impl Cell<'_> {
+    fn unsigned<'de, V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, Error> {
+        match self.0.trim().parse() {
+            Ok(value) => visitor.visit_u64(value),
+            Err(_) => Err(de::Error::invalid_value(
+                Unexpected::Str(self.0),
+                &"an unsigned integer",
+            )),
+        }
+    }
+}
+
+impl<'de> Deserializer<'de> for Cell<'_> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, Error> {
+        visitor.visit_str(self.0)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> std::result::Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, Error> {
+        self.unsigned(visitor)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, Error> {
+        self.unsigned(visitor)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, Error> {
+        self.unsigned(visitor)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, Error> {
+        self.unsigned(visitor)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, Error> {
+        let items = self
+            .0
+            .trim()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .split([' ', ';', ','])
+            .filter(|item| !item.is_empty())
+            .map(Cell);
+        visitor.visit_seq(SeqDeserializer::new(items))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u128 f32 f64 char str string bytes byte_buf unit unit_struct
+        newtype_struct tuple tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+impl<'de, 'a> IntoDeserializer<'de, Error> for Cell<'a> {
+    type Deserializer = Cell<'a>;
+
+    fn into_deserializer(self) -> Cell<'a> {
+        self
+    }
+}
@@ -0,0 +1,61 @@
+//! Records that could not be decoded, kept in `DEAD_LETTER_FILE` for replay or debugging.
+
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncWriteExt, BufWriter},
+};
+
+use crate::{
+    error::{FlowError, Result},
+    input::InputFormat,
+};
+
+/// Appends rejected records through a buffer, which is flushed on the batch flush timer and
+/// at shutdown rather than per record.
+pub struct DeadLetter {
+    writer: BufWriter<File>,
+    format: InputFormat,
+}
+
+impl DeadLetter {
+    pub async fn open(path: &str, format: InputFormat) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .map_err(|e| {
+                FlowError::Config(format!("Failed to open dead letter file {}: {}", path, e))
+            })?;
+
+        Ok(DeadLetter {
+            writer: BufWriter::new(file),
+            format,
+        })
+    }
+
+    /// JSON records are written verbatim after a `# <error>` comment line, so the file can be
+    /// replayed with `grep -v '^#'` (as `json`, for array elements too). CSV rows are written
+    /// one per line without their header, protobuf records as-is (still length-delimited, so
+    /// the file is valid protobuf input); the errors of both are only logged.
+    pub async fn write(&mut self, record: &[u8], error: &FlowError) -> Result<()> {
+        match self.format {
+            InputFormat::Json | InputFormat::JsonArray => {
+                let comment = format!("# {}\n", error.to_string().replace('\n', " "));
+                self.writer.write_all(comment.as_bytes()).await?;
+                self.writer.write_all(record).await?;
+                self.writer.write_all(b"\n").await?;
+            }
+            InputFormat::Csv => {
+                self.writer.write_all(record).await?;
+                self.writer.write_all(b"\n").await?;
+            }
+            InputFormat::Protobuf => self.writer.write_all(record).await?,
+        }
+        Ok(())
+    }
+
+    pub async fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush().await?)
+    }
+}
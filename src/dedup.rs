@@ -0,0 +1,48 @@
+//! Skipping of flows an exporter sent more than once (`DEDUP_WINDOW`).
+
+use lru::LruCache;
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    num::NonZeroUsize,
+};
+
+use crate::FlowData;
+
+/// Remembers the last `window` flows seen.
+///
+/// Flows are identified by `(sampler_address, sequence_num)`, refined with the flow's own
+/// addresses, ports, protocol and start time: exporters number export packets rather than
+/// flows, so every flow of one packet shares a sequence number. Only exact repeats within
+/// the window count as duplicates, which keeps `u32` sequence number wraparound harmless:
+/// a reused number is a different flow long after the original has been evicted.
+pub struct Deduplicator {
+    seen: LruCache<u64, ()>,
+}
+
+impl Deduplicator {
+    pub fn new(window: NonZeroUsize) -> Self {
+        Deduplicator {
+            seen: LruCache::new(window),
+        }
+    }
+
+    /// Records `flow`, returning whether it was already in the window.
+    pub fn is_duplicate(&mut self, flow: &FlowData) -> bool {
+        // A 64-bit digest keeps the window compact; collisions are negligible at any
+        // practical window size
+        let mut hasher = DefaultHasher::new();
+        (
+            &flow.sampler_address,
+            flow.sequence_num,
+            &flow.src_addr,
+            &flow.dst_addr,
+            flow.src_port,
+            flow.dst_port,
+            &flow.proto,
+            flow.time_flow_start_ns,
+        )
+            .hash(&mut hasher);
+
+        self.seen.put(hasher.finish(), ()).is_some()
+    }
+}
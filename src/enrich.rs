@@ -0,0 +1,381 @@
+//! Optional per-flow enrichment from external data sources.
+
+use hickory_resolver::{TokioResolver, proto::rr::RData};
+use ipnet::IpNet;
+use lru::LruCache;
+use maxminddb::{Reader, geoip2};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    num::NonZeroUsize,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::Semaphore;
+
+use crate::{
+    Config, FlowData,
+    cardinality::CardinalityLimiter,
+    default_private_ranges,
+    error::{FlowError, Result},
+    is_private_ip,
+    point::PointBuilder,
+};
+
+/// Lookup state shared by every flow, loaded once at startup.
+pub struct Enrichment {
+    geoip: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+    reverse_dns: Option<ReverseDns>,
+    /// Interface names by sampler address, then by SNMP index
+    ifindex_names: Option<HashMap<String, HashMap<String, String>>>,
+    sampler_names: Option<HashMap<String, String>>,
+    mac_vendors: Option<OuiTable>,
+    as_tags_enabled: bool,
+    non_routable: Vec<IpNet>,
+}
+
+impl Enrichment {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let geoip = config
+            .geoip_db_path
+            .as_deref()
+            .map(|path| open_database(path, "GeoIP"))
+            .transpose()?;
+        let asn = config
+            .asn_db_path
+            .as_deref()
+            .map(|path| open_database(path, "ASN"))
+            .transpose()?;
+        let reverse_dns = if config.reverse_dns_enabled {
+            Some(ReverseDns::new(
+                config.reverse_dns_cache_size,
+                config.reverse_dns_concurrency,
+                Duration::from_millis(config.reverse_dns_timeout_ms),
+            )?)
+        } else {
+            None
+        };
+        let ifindex_names = config
+            .ifindex_map
+            .as_deref()
+            .map(|path| read_json_file(path, "IFINDEX_MAP"))
+            .transpose()?;
+        // Given inline as a JSON object, or as the path of a file holding one
+        let sampler_names = config
+            .sampler_names
+            .as_deref()
+            .map(|value| {
+                if value.trim_start().starts_with('{') {
+                    serde_json::from_str(value).map_err(|e| {
+                        FlowError::Config(format!("Failed to parse SAMPLER_NAMES: {}", e))
+                    })
+                } else {
+                    read_json_file(value, "SAMPLER_NAMES")
+                }
+            })
+            .transpose()?;
+        let mac_vendors = config
+            .mac_vendor_enabled
+            .then(|| read_oui_table(config.mac_vendor_db.as_deref()))
+            .transpose()?;
+
+        Ok(Enrichment {
+            geoip,
+            asn,
+            reverse_dns,
+            ifindex_names,
+            sampler_names,
+            mac_vendors,
+            as_tags_enabled: config.as_tags_enabled,
+            non_routable: default_private_ranges(),
+        })
+    }
+
+    pub fn apply(
+        &self,
+        mut builder: PointBuilder,
+        flow: &FlowData,
+        tags: &CardinalityLimiter,
+    ) -> PointBuilder {
+        if let Some(reader) = &self.geoip {
+            builder = enrich_geo(builder, flow, reader, &self.non_routable, tags);
+        }
+        if let Some(reader) = &self.asn {
+            builder = self.enrich_asn(builder, flow, reader, tags);
+        }
+        if let Some(reverse_dns) = &self.reverse_dns {
+            builder = tags.tag(builder, "src_host", reverse_dns.hostname(&flow.src_addr));
+            builder = tags.tag(builder, "dst_host", reverse_dns.hostname(&flow.dst_addr));
+        }
+        if let Some(name) = self
+            .sampler_names
+            .as_ref()
+            .and_then(|names| names.get(&flow.sampler_address))
+        {
+            builder = tags.tag(builder, "sampler_name", name);
+        }
+        if let Some(ifindex_names) = &self.ifindex_names {
+            let names = ifindex_names.get(&flow.sampler_address);
+            for (key, index) in [("in_if_name", flow.in_if), ("out_if_name", flow.out_if)] {
+                let index = index.to_string();
+                let name = names.and_then(|names| names.get(&index)).unwrap_or(&index);
+                builder = tags.tag(builder, key, name);
+            }
+        }
+        if let Some(vendors) = &self.mac_vendors {
+            for (prefix, mac) in [("src", &flow.src_mac), ("dst", &flow.dst_mac)] {
+                let Some(mac) = mac.as_deref().filter(|mac| !mac.is_empty()) else {
+                    continue;
+                };
+                let vendor = mac_oui(mac)
+                    .and_then(|oui| vendors.get(&oui))
+                    .map_or("unknown", String::as_str);
+                builder = tags.tag(builder, format!("{}_vendor", prefix), vendor);
+            }
+        }
+        builder
+    }
+
+    /// Fills in `src_as`/`dst_as` for flows the exporter sent without them, written the
+    /// same way as exporter-provided AS numbers, plus an `*_as_org` field.
+    fn enrich_asn(
+        &self,
+        mut builder: PointBuilder,
+        flow: &FlowData,
+        reader: &Reader<Vec<u8>>,
+        tags: &CardinalityLimiter,
+    ) -> PointBuilder {
+        let sides = [
+            ("src", &flow.src_addr, flow.src_as),
+            ("dst", &flow.dst_addr, flow.dst_as),
+        ];
+
+        for (prefix, addr, exported_as) in sides {
+            if exported_as.is_some() || is_private_ip(addr, &self.non_routable) {
+                continue;
+            }
+
+            let Some((asn, org)) = lookup_asn(reader, addr) else {
+                continue;
+            };
+
+            let key = format!("{}_as", prefix);
+            builder = if self.as_tags_enabled {
+                tags.tag(builder, key, asn.to_string())
+            } else {
+                builder.field(key, asn as i64)
+            };
+            if let Some(org) = org {
+                builder = builder.field(format!("{}_as_org", prefix), org);
+            }
+        }
+        builder
+    }
+}
+
+fn open_database(path: &str, kind: &str) -> Result<Reader<Vec<u8>>> {
+    Reader::open_readfile(path).map_err(|e| {
+        FlowError::Config(format!(
+            "Failed to open {} database '{}': {}",
+            kind, path, e
+        ))
+    })
+}
+
+fn read_json_file<T: serde::de::DeserializeOwned>(path: &str, setting: &str) -> Result<T> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| FlowError::Config(format!("Failed to read {} '{}': {}", setting, path, e)))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| FlowError::Config(format!("Failed to parse {} '{}': {}", setting, path, e)))
+}
+
+/// Vendor names by OUI, the first three octets of a MAC address
+type OuiTable = HashMap<u32, String>;
+
+const BUNDLED_OUI: &str = include_str!("oui.txt");
+
+/// Reads the OUI database at `path`, or the bundled table when there is none.
+fn read_oui_table(path: Option<&str>) -> Result<OuiTable> {
+    let Some(path) = path else {
+        return Ok(parse_oui_table(BUNDLED_OUI));
+    };
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        FlowError::Config(format!("Failed to read MAC_VENDOR_DB '{}': {}", path, e))
+    })?;
+    let table = parse_oui_table(&contents);
+    if table.is_empty() {
+        return Err(FlowError::Config(format!(
+            "No OUI entries found in MAC_VENDOR_DB '{}'",
+            path
+        )));
+    }
+    Ok(table)
+}
+
+/// Parses lines starting with an OUI followed by its vendor, as in the bundled table,
+/// Wireshark's `manuf` (the short name, before a tab) and IEEE's `oui.txt` (after `(hex)` or
+/// `(base 16)`). Longer prefixes, such as `manuf`'s `/28` and `/36` blocks, are skipped.
+fn parse_oui_table(contents: &str) -> OuiTable {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (prefix, rest) = line.trim().split_once(char::is_whitespace)?;
+            let hex: String = prefix
+                .chars()
+                .filter(|c| !matches!(c, ':' | '-' | '.'))
+                .collect();
+            if hex.len() != 6 {
+                return None;
+            }
+            let oui = u32::from_str_radix(&hex, 16).ok()?;
+
+            let rest = rest.trim_start();
+            let rest = ["(hex)", "(base 16)"]
+                .into_iter()
+                .find_map(|label| rest.strip_prefix(label))
+                .unwrap_or(rest);
+            let name = rest.trim_start().split('\t').next()?.trim();
+            (!name.is_empty()).then(|| (oui, name.to_string()))
+        })
+        .collect()
+}
+
+/// The OUI of `mac`, its first three octets, however they are separated.
+fn mac_oui(mac: &str) -> Option<u32> {
+    let hex: String = mac
+        .chars()
+        .filter(char::is_ascii_hexdigit)
+        .take(6)
+        .collect();
+    if hex.len() < 6 {
+        return None;
+    }
+    u32::from_str_radix(&hex, 16).ok()
+}
+
+/// Adds `src_country`/`dst_country` (and `*_city` when the database has city data) tags.
+///
+/// Non-routable addresses are skipped; addresses the database can't resolve are
+/// tagged `unknown`.
+pub fn enrich_geo(
+    mut builder: PointBuilder,
+    flow: &FlowData,
+    reader: &Reader<Vec<u8>>,
+    non_routable: &[IpNet],
+    tags: &CardinalityLimiter,
+) -> PointBuilder {
+    for (prefix, addr) in [("src", &flow.src_addr), ("dst", &flow.dst_addr)] {
+        if is_private_ip(addr, non_routable) {
+            continue;
+        }
+
+        let (country, city) = lookup_geo(reader, addr);
+        builder = tags.tag(builder, format!("{}_country", prefix), country);
+        if let Some(city) = city {
+            builder = tags.tag(builder, format!("{}_city", prefix), city);
+        }
+    }
+    builder
+}
+
+fn lookup_geo(reader: &Reader<Vec<u8>>, addr: &str) -> (String, Option<String>) {
+    let record = IpAddr::from_str(addr)
+        .ok()
+        .and_then(|ip| reader.lookup(ip.to_canonical()).ok())
+        .and_then(|result| result.decode::<geoip2::City>().ok().flatten());
+
+    match record {
+        Some(record) => (
+            record.country.iso_code.unwrap_or("unknown").to_string(),
+            record.city.names.english.map(str::to_string),
+        ),
+        None => ("unknown".to_string(), None),
+    }
+}
+
+fn lookup_asn(reader: &Reader<Vec<u8>>, addr: &str) -> Option<(u32, Option<String>)> {
+    let ip = IpAddr::from_str(addr).ok()?;
+    let record = reader
+        .lookup(ip.to_canonical())
+        .ok()?
+        .decode::<geoip2::Asn>()
+        .ok()
+        .flatten()?;
+
+    Some((
+        record.autonomous_system_number?,
+        record.autonomous_system_organization.map(str::to_string),
+    ))
+}
+
+/// PTR resolver backed by a bounded LRU cache.
+///
+/// Lookups never block the flow being converted: a cache miss starts a background lookup
+/// (at most `concurrency` at a time) and the raw address is used until it completes. Failed
+/// and timed-out lookups are cached too, so unresolvable addresses aren't retried per flow.
+pub struct ReverseDns {
+    resolver: TokioResolver,
+    cache: Arc<Mutex<LruCache<IpAddr, Option<String>>>>,
+    lookups: Arc<Semaphore>,
+    timeout: Duration,
+}
+
+impl ReverseDns {
+    pub fn new(cache_size: NonZeroUsize, concurrency: usize, timeout: Duration) -> Result<Self> {
+        let resolver = TokioResolver::builder_tokio()
+            .and_then(|builder| builder.build())
+            .map_err(|e| FlowError::Config(format!("Failed to create DNS resolver: {}", e)))?;
+
+        Ok(ReverseDns {
+            resolver,
+            cache: Arc::new(Mutex::new(LruCache::new(cache_size))),
+            lookups: Arc::new(Semaphore::new(concurrency)),
+            timeout,
+        })
+    }
+
+    /// Returns the cached hostname for `addr`, or `addr` itself when none is known yet.
+    pub fn hostname(&self, addr: &str) -> String {
+        let Ok(ip) = IpAddr::from_str(addr) else {
+            return addr.to_string();
+        };
+
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(entry) = cache.get(&ip) {
+            return entry.clone().unwrap_or_else(|| addr.to_string());
+        }
+
+        // When every lookup slot is busy the address is simply tried again on a later flow
+        if let Ok(permit) = self.lookups.clone().try_acquire_owned() {
+            cache.put(ip, None);
+
+            let resolver = self.resolver.clone();
+            let cache = self.cache.clone();
+            let timeout = self.timeout;
+            tokio::spawn(async move {
+                let hostname = lookup_ptr(&resolver, ip, timeout).await;
+                cache.lock().unwrap().put(ip, hostname);
+                drop(permit);
+            });
+        }
+
+        addr.to_string()
+    }
+}
+
+async fn lookup_ptr(resolver: &TokioResolver, ip: IpAddr, timeout: Duration) -> Option<String> {
+    let lookup = tokio::time::timeout(timeout, resolver.reverse_lookup(ip))
+        .await
+        .ok()?
+        .ok()?;
+
+    lookup
+        .answers()
+        .iter()
+        .find_map(|record| match &record.data {
+            RData::PTR(ptr) => Some(ptr.0.to_utf8().trim_end_matches('.').to_string()),
+            _ => None,
+        })
+}
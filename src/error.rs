@@ -0,0 +1,48 @@
+//! The error type shared by every module; `main` reports it through `anyhow`.
+
+use thiserror::Error;
+
+/// What went wrong, by kind, so callers can tell a misconfiguration from a bad record or an
+/// unreachable server. Each variant carries the message that is shown to the user.
+#[derive(Debug, Error)]
+pub enum FlowError {
+    /// A setting is missing or invalid
+    #[error("{0}")]
+    Config(String),
+    /// A record could not be decoded
+    #[error("{0}")]
+    Parse(String),
+    /// A decoded flow could not be turned into a point
+    #[error("{0}")]
+    Conversion(String),
+    /// A JSON record is well-formed but doesn't match the flow schema (`STRICT_SCHEMA`)
+    #[error("{0}")]
+    Schema(String),
+    /// A write was rejected by InfluxDB, or still failed after every retry
+    #[error("{0}")]
+    Write(String),
+    /// A server (InfluxDB, Kafka, a listening socket) could not be reached or bound
+    #[error("{0}")]
+    Connectivity(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl From<influxdb2::RequestError> for FlowError {
+    fn from(e: influxdb2::RequestError) -> Self {
+        match e {
+            influxdb2::RequestError::ReqwestProcessing { .. } => {
+                FlowError::Connectivity(e.to_string())
+            }
+            _ => FlowError::Write(e.to_string()),
+        }
+    }
+}
+
+impl From<reqwest::Error> for FlowError {
+    fn from(e: reqwest::Error) -> Self {
+        FlowError::Connectivity(e.to_string())
+    }
+}
+
+pub type Result<T, E = FlowError> = std::result::Result<T, E>;
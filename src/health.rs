@@ -0,0 +1,106 @@
+//! Liveness and readiness endpoints for orchestrators, served on `HEALTH_ADDR`.
+
+use axum::{Router, extract::State, http::StatusCode, routing::get};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+    },
+    time::Duration,
+};
+use tokio::{net::TcpListener, time::sleep};
+use tracing::{error, info, warn};
+
+use crate::{
+    error::{FlowError, Result},
+    sink::Sink,
+};
+
+pub static HEALTH: Health = Health::new();
+
+/// How often the startup connectivity check is retried until InfluxDB answers.
+const CONNECTIVITY_RETRY: Duration = Duration::from_secs(5);
+
+pub struct Health {
+    influxdb_reachable: AtomicBool,
+    consecutive_write_failures: AtomicU32,
+}
+
+impl Health {
+    const fn new() -> Self {
+        Health {
+            influxdb_reachable: AtomicBool::new(false),
+            consecutive_write_failures: AtomicU32::new(0),
+        }
+    }
+
+    /// Records the final outcome of a batch write, after retries.
+    pub fn record_write(&self, succeeded: bool) {
+        if succeeded {
+            self.influxdb_reachable.store(true, Ordering::Relaxed);
+            self.consecutive_write_failures.store(0, Ordering::Relaxed);
+        } else {
+            self.consecutive_write_failures
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Ready once InfluxDB has been reached, until `failure_threshold` writes in a row fail
+    /// (0 ignores write failures).
+    fn is_ready(&self, failure_threshold: u32) -> bool {
+        let failures = self.consecutive_write_failures.load(Ordering::Relaxed);
+        self.influxdb_reachable.load(Ordering::Relaxed)
+            && (failure_threshold == 0 || failures < failure_threshold)
+    }
+}
+
+async fn readyz(State(failure_threshold): State<u32>) -> StatusCode {
+    if HEALTH.is_ready(failure_threshold) {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+/// Binds `addr`, serves `/healthz` and `/readyz` on a background task, and checks InfluxDB
+/// connectivity in the background until it succeeds.
+pub async fn serve(addr: &str, sink: Arc<dyn Sink>, failure_threshold: u32) -> Result<()> {
+    let listener = TcpListener::bind(addr).await.map_err(|e| {
+        FlowError::Connectivity(format!("Failed to bind health server {}: {}", addr, e))
+    })?;
+    info!(
+        "Serving health checks on http://{}/healthz and /readyz",
+        listener.local_addr()?
+    );
+
+    let app = Router::new()
+        .route("/healthz", get(|| async { StatusCode::OK }))
+        .route("/readyz", get(readyz))
+        .with_state(failure_threshold);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("Health server failed: {}", e);
+        }
+    });
+
+    tokio::spawn(async move {
+        loop {
+            match sink.ping().await {
+                Ok(()) => {
+                    info!("InfluxDB connectivity check succeeded");
+                    HEALTH.influxdb_reachable.store(true, Ordering::Relaxed);
+                    return;
+                }
+                Err(e) => {
+                    warn!(
+                        "InfluxDB connectivity check failed: {}. Retrying in {}s...",
+                        e,
+                        CONNECTIVITY_RETRY.as_secs()
+                    );
+                    sleep(CONNECTIVITY_RETRY).await;
+                }
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,880 @@
+//! Sources of raw GoFlow2 flow records.
+
+use async_compression::tokio::bufread::GzipDecoder;
+use std::{
+    collections::VecDeque,
+    io::{self, SeekFrom},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+use tokio::{
+    io::{
+        AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader,
+        DuplexStream,
+    },
+    net::{TcpListener, UdpSocket},
+    sync::mpsc,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    Config,
+    csv::{self, CsvHeader},
+    error::{FlowError, Result},
+    metrics::METRICS,
+};
+
+/// Largest possible UDP payload, so no datagram is ever truncated.
+const MAX_DATAGRAM_SIZE: usize = 65_535;
+
+type ByteStream = Box<dyn AsyncRead + Unpin + Send>;
+
+/// Upper bound on one length-delimited protobuf record, so a corrupt length prefix fails
+/// fast instead of buffering the rest of the input.
+const MAX_PROTOBUF_RECORD_SIZE: usize = 1 << 20;
+
+/// UTF-8 byte order mark, which some tools write at the start of text files
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// How often a followed file is checked for new data once its end was reached
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Records buffered between TCP connection tasks and the batching loop.
+const TCP_CHANNEL_CAPACITY: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputMode {
+    /// Newline-delimited JSON from `GOFLOW2_INPUT_FILE` (stdin by default), which may also be
+    /// a comma-separated list of files and/or glob patterns
+    File,
+    /// One JSON record per datagram received on `UDP_LISTEN_ADDR`
+    Udp,
+    /// Newline-delimited JSON from any number of connections to `TCP_LISTEN_ADDR`
+    Tcp,
+    /// JSON messages consumed from `KAFKA_TOPIC` (requires the `kafka` feature)
+    Kafka,
+}
+
+impl FromStr for InputMode {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "file" => Ok(InputMode::File),
+            "udp" => Ok(InputMode::Udp),
+            "tcp" => Ok(InputMode::Tcp),
+            "kafka" => Ok(InputMode::Kafka),
+            other => Err(FlowError::Config(format!(
+                "Invalid INPUT_MODE '{}', expected one of: file, udp, tcp, kafka",
+                other
+            ))),
+        }
+    }
+}
+
+/// How records are encoded; see `RecordReader` for how streams are split into records.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    /// One JSON object per line (goflow2 `-format=json`)
+    Json,
+    /// JSON arrays of flow objects, streamed element by element
+    JsonArray,
+    /// Length-delimited `FlowMessage`s (goflow2 `-format=bin`)
+    Protobuf,
+    /// A header row naming the fields, then one flow per row
+    Csv,
+}
+
+impl FromStr for InputFormat {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "json" => Ok(InputFormat::Json),
+            "json-array" | "json_array" => Ok(InputFormat::JsonArray),
+            "protobuf" | "proto" | "bin" => Ok(InputFormat::Protobuf),
+            "csv" => Ok(InputFormat::Csv),
+            other => Err(FlowError::Config(format!(
+                "Invalid INPUT_FORMAT '{}', expected one of: json, json-array, protobuf, csv",
+                other
+            ))),
+        }
+    }
+}
+
+pub enum Input {
+    Stream(RecordReader),
+    /// Several files read one after another
+    Files(FileSequence),
+    Udp {
+        socket: UdpSocket,
+        buf: Vec<u8>,
+    },
+    /// Lines from all TCP connections, merged by per-connection tasks
+    Tcp(mpsc::Receiver<Vec<u8>>),
+    #[cfg(feature = "kafka")]
+    Kafka(kafka::KafkaInput),
+}
+
+impl Input {
+    pub async fn open(config: &Config) -> Result<Self> {
+        match config.input_mode {
+            InputMode::File => {
+                let spec = &config.goflow2_input_file;
+                if spec.contains(',') || spec.contains(['*', '?', '[']) {
+                    let paths = expand_input_files(spec)?;
+                    return Ok(Input::Files(FileSequence::new(paths, config)));
+                }
+
+                let limits = ReadLimits::new(config);
+                let input: ByteStream = if spec == "/dev/stdin" {
+                    if config.follow {
+                        warn!("FOLLOW has no effect on stdin, which is read until it is closed");
+                    }
+                    Box::new(tokio::io::stdin())
+                } else {
+                    let file = tokio::fs::File::open(spec).await?;
+                    if !config.follow {
+                        Box::new(file)
+                    } else if file.metadata().await?.is_file() {
+                        follow(PathBuf::from(spec), file, limits)
+                    } else {
+                        warn!(
+                            "FOLLOW has no effect on {}, which is not a regular file",
+                            spec
+                        );
+                        Box::new(file)
+                    }
+                };
+                let gzip = config.input_gzip || is_gzip_path(Path::new(spec));
+                Ok(Input::Stream(RecordReader::new(
+                    input,
+                    config.input_format,
+                    limits,
+                    gzip,
+                )))
+            }
+            InputMode::Udp => {
+                let socket = UdpSocket::bind(&config.udp_listen_addr)
+                    .await
+                    .map_err(|e| {
+                        FlowError::Connectivity(format!(
+                            "Failed to bind UDP {}: {}",
+                            config.udp_listen_addr, e
+                        ))
+                    })?;
+                info!(
+                    "Listening for flow datagrams on udp://{}",
+                    socket.local_addr()?
+                );
+                Ok(Input::Udp {
+                    socket,
+                    buf: vec![0; MAX_DATAGRAM_SIZE],
+                })
+            }
+            InputMode::Tcp => {
+                let listener = TcpListener::bind(&config.tcp_listen_addr)
+                    .await
+                    .map_err(|e| {
+                        FlowError::Connectivity(format!(
+                            "Failed to bind TCP {}: {}",
+                            config.tcp_listen_addr, e
+                        ))
+                    })?;
+                info!(
+                    "Listening for flow connections on tcp://{}",
+                    listener.local_addr()?
+                );
+
+                let (tx, rx) = mpsc::channel(TCP_CHANNEL_CAPACITY);
+                tokio::spawn(accept_connections(
+                    listener,
+                    config.input_format,
+                    ReadLimits::new(config),
+                    tx,
+                ));
+                Ok(Input::Tcp(rx))
+            }
+            #[cfg(feature = "kafka")]
+            InputMode::Kafka => Ok(Input::Kafka(kafka::KafkaInput::new(config)?)),
+            #[cfg(not(feature = "kafka"))]
+            InputMode::Kafka => Err(FlowError::Config(
+                "INPUT_MODE=kafka requires building with `--features kafka`".to_string(),
+            )),
+        }
+    }
+
+    /// Returns the next raw record, still encoded in `INPUT_FORMAT`, or `None` once the
+    /// input is exhausted.
+    ///
+    /// Cancel safe, so it can be used as a `tokio::select!` branch.
+    pub async fn next_record(&mut self) -> Result<Option<Vec<u8>>> {
+        match self {
+            Input::Stream(reader) => reader.next_record().await,
+            Input::Files(files) => files.next_record().await,
+            Input::Udp { socket, buf } => {
+                let (len, _) = socket.recv_from(buf).await?;
+                Ok(Some(buf[..len].to_vec()))
+            }
+            Input::Tcp(rx) => Ok(rx.recv().await),
+            #[cfg(feature = "kafka")]
+            Input::Kafka(kafka) => kafka.next_record().await,
+        }
+    }
+
+    /// The file currently being read, when reading a sequence of files.
+    pub fn current_file(&self) -> Option<&str> {
+        match self {
+            Input::Files(files) => files.current.as_ref().map(|(name, _)| name.as_str()),
+            _ => None,
+        }
+    }
+
+    /// The header of the CSV stream currently being read, once its first row was read.
+    pub fn csv_header(&self) -> Option<CsvHeader> {
+        match self {
+            Input::Stream(reader) => reader.csv_header(),
+            Input::Files(files) => files
+                .current
+                .as_ref()
+                .and_then(|(_, reader)| reader.csv_header()),
+            _ => None,
+        }
+    }
+
+    /// Whether records must be acknowledged once written, and so must never be dropped.
+    pub fn tracks_delivery(&self) -> bool {
+        match self {
+            #[cfg(feature = "kafka")]
+            Input::Kafka(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Marks the first `through` records returned as durably written.
+    #[cfg_attr(not(feature = "kafka"), allow(unused_variables))]
+    pub fn acknowledge(&mut self, through: u64) -> Result<()> {
+        match self {
+            #[cfg(feature = "kafka")]
+            Input::Kafka(kafka) => kafka.commit(through),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn is_gzip_path(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "gz")
+}
+
+/// Splits a byte stream into records: lines for JSON, array elements for JSON arrays, lines
+/// after the header for CSV, length-delimited messages (prefix included) for protobuf.
+pub enum RecordReader {
+    Lines(LineReader),
+    JsonArray {
+        stream: ByteStream,
+        splitter: ArraySplitter,
+    },
+    Csv {
+        lines: LineReader,
+        header: Option<CsvHeader>,
+    },
+    Delimited {
+        stream: ByteStream,
+        buf: Vec<u8>,
+        buffer_bytes: usize,
+    },
+}
+
+impl RecordReader {
+    fn new(stream: ByteStream, format: InputFormat, limits: ReadLimits, gzip: bool) -> Self {
+        let stream: ByteStream = if gzip {
+            // Concatenated gzip members (e.g. appended logs) are read as one stream
+            let mut decoder = GzipDecoder::new(BufReader::new(stream));
+            decoder.multiple_members(true);
+            Box::new(decoder)
+        } else {
+            stream
+        };
+
+        match format {
+            InputFormat::Json => RecordReader::Lines(LineReader::new(stream, limits)),
+            InputFormat::JsonArray => RecordReader::JsonArray {
+                stream,
+                splitter: ArraySplitter::new(limits),
+            },
+            InputFormat::Csv => RecordReader::Csv {
+                lines: LineReader::new(stream, limits),
+                header: None,
+            },
+            InputFormat::Protobuf => RecordReader::Delimited {
+                stream,
+                buf: Vec::new(),
+                buffer_bytes: limits.buffer_bytes,
+            },
+        }
+    }
+
+    /// Cancel safe: a partially read record stays buffered for the next call.
+    async fn next_record(&mut self) -> Result<Option<Vec<u8>>> {
+        match self {
+            RecordReader::Lines(lines) => lines.next_line().await,
+            RecordReader::JsonArray { stream, splitter } => loop {
+                if let Some(element) = splitter.next_element()? {
+                    return Ok(Some(element));
+                }
+
+                splitter.buf.reserve(splitter.limits.buffer_bytes);
+                if stream.read_buf(&mut splitter.buf).await? == 0 {
+                    if splitter.is_idle() {
+                        return Ok(None);
+                    }
+                    return Err(FlowError::Parse(
+                        "Input ended in the middle of a JSON array".to_string(),
+                    ));
+                }
+            },
+            RecordReader::Csv { lines, header } => loop {
+                let Some(line) = lines.next_line().await? else {
+                    return Ok(None);
+                };
+                if header.is_some() {
+                    return Ok(Some(line));
+                }
+                // Blank lines before the header are skipped
+                if !line.trim_ascii().is_empty() {
+                    *header = Some(csv::parse_header(&String::from_utf8_lossy(&line)));
+                }
+            },
+            RecordReader::Delimited {
+                stream,
+                buf,
+                buffer_bytes,
+            } => loop {
+                if let Some(len) = delimited_record_len(buf)? {
+                    return Ok(Some(buf.drain(..len).collect()));
+                }
+
+                buf.reserve(*buffer_bytes);
+                if stream.read_buf(buf).await? == 0 {
+                    if buf.is_empty() {
+                        return Ok(None);
+                    }
+                    return Err(FlowError::Parse(
+                        "Input ended in the middle of a protobuf record".to_string(),
+                    ));
+                }
+            },
+        }
+    }
+
+    fn csv_header(&self) -> Option<CsvHeader> {
+        match self {
+            RecordReader::Csv { header, .. } => header.clone(),
+            _ => None,
+        }
+    }
+}
+
+/// Read buffer size and record length limit of stream inputs (`READ_BUFFER_BYTES` and
+/// `MAX_LINE_BYTES`).
+#[derive(Debug, Clone, Copy)]
+pub struct ReadLimits {
+    buffer_bytes: usize,
+    max_line_bytes: usize,
+}
+
+impl ReadLimits {
+    fn new(config: &Config) -> Self {
+        ReadLimits {
+            buffer_bytes: config.read_buffer_bytes,
+            max_line_bytes: config.max_line_bytes,
+        }
+    }
+}
+
+/// Splits a stream into lines. Of lines longer than `MAX_LINE_BYTES`, one byte more is kept,
+/// so they fail to decode, and the rest discarded, so a single huge line can't exhaust memory.
+pub struct LineReader {
+    reader: BufReader<ByteStream>,
+    line: Vec<u8>,
+    max_line_bytes: usize,
+}
+
+impl LineReader {
+    fn new(stream: ByteStream, limits: ReadLimits) -> Self {
+        LineReader {
+            reader: BufReader::with_capacity(limits.buffer_bytes, stream),
+            line: Vec::new(),
+            max_line_bytes: limits.max_line_bytes,
+        }
+    }
+
+    /// Cancel safe: a partially read line stays buffered for the next call.
+    async fn next_line(&mut self) -> Result<Option<Vec<u8>>> {
+        loop {
+            let available = self.reader.fill_buf().await?;
+            if available.is_empty() {
+                // The last line needn't end in a newline
+                if self.line.is_empty() {
+                    return Ok(None);
+                }
+                return Ok(Some(clean_line(std::mem::take(&mut self.line))));
+            }
+
+            let newline = available.iter().position(|&byte| byte == b'\n');
+            let end = newline.unwrap_or(available.len());
+            let room = (self.max_line_bytes + 1).saturating_sub(self.line.len());
+            self.line.extend_from_slice(&available[..end.min(room)]);
+            self.reader.consume(newline.map_or(end, |i| i + 1));
+            if newline.is_some() {
+                return Ok(Some(clean_line(std::mem::take(&mut self.line))));
+            }
+        }
+    }
+}
+
+/// A line without any `\r` at its end (from `\r\n`, or `\r\r\n` after a double conversion),
+/// which would end up in a CSV row's last cell, or a byte order mark at its start. Marks are
+/// stripped from any line rather than just the first, as concatenated files carry one per file.
+fn clean_line(mut line: Vec<u8>) -> Vec<u8> {
+    if line.starts_with(BOM) {
+        METRICS.byte_order_marks.inc();
+        line.drain(..BOM.len());
+    }
+    while line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    line
+}
+
+/// Splits JSON arrays (`[{...},{...}]`, possibly several one after another) into their
+/// elements without parsing them, so only the element being read is buffered.
+pub struct ArraySplitter {
+    limits: ReadLimits,
+    buf: Vec<u8>,
+    /// How far `buf` has been scanned
+    pos: usize,
+    /// Where the element being scanned starts in `buf`
+    start: Option<usize>,
+    in_array: bool,
+    /// Nesting within the element being scanned
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl ArraySplitter {
+    fn new(limits: ReadLimits) -> Self {
+        ArraySplitter {
+            limits,
+            buf: Vec::new(),
+            pos: 0,
+            start: None,
+            in_array: false,
+            depth: 0,
+            in_string: false,
+            escaped: false,
+        }
+    }
+
+    /// The next complete element in `buf`, or `None` while more bytes are needed.
+    fn next_element(&mut self) -> Result<Option<Vec<u8>>> {
+        while let Some(&byte) = self.buf.get(self.pos) {
+            self.pos += 1;
+            if !self.in_string && self.start.is_none() && byte == BOM[0] {
+                let rest = &self.buf[self.pos - 1..];
+                if rest.len() < BOM.len() && BOM.starts_with(rest) {
+                    // Keep the start of the mark until the rest of it is read
+                    self.buf.drain(..self.pos - 1);
+                    self.pos = 0;
+                    return Ok(None);
+                }
+                if rest.starts_with(BOM) {
+                    METRICS.byte_order_marks.inc();
+                    self.pos += BOM.len() - 1;
+                    continue;
+                }
+            }
+            if self.in_string {
+                match byte {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => self.in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+
+            match byte {
+                _ if byte.is_ascii_whitespace() => {}
+                b'[' if !self.in_array => self.in_array = true,
+                _ if !self.in_array => {
+                    return Err(FlowError::Parse(format!(
+                        "Expected a JSON array, found '{}'",
+                        char::from(byte)
+                    )));
+                }
+                b',' | b']' if self.depth == 0 => {
+                    self.in_array = byte == b',';
+                    if let Some(start) = self.start.take() {
+                        let element = self.buf[start..self.pos - 1].trim_ascii_end().to_vec();
+                        self.buf.drain(..self.pos);
+                        self.pos = 0;
+                        return Ok(Some(element));
+                    }
+                }
+                _ => {
+                    self.start.get_or_insert(self.pos - 1);
+                    match byte {
+                        b'{' | b'[' => self.depth += 1,
+                        b'}' | b']' => self.depth = self.depth.saturating_sub(1),
+                        b'"' => self.in_string = true,
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        // Nothing scanned so far is needed again unless an element is under way, and of
+        // elements longer than `MAX_LINE_BYTES` only as much as `LineReader` keeps of a line
+        match self.start {
+            None => self.buf.clear(),
+            Some(start) => self.buf.truncate(start + self.limits.max_line_bytes + 1),
+        }
+        self.pos = self.buf.len();
+        Ok(None)
+    }
+
+    /// Whether the input may end here, outside any array.
+    fn is_idle(&self) -> bool {
+        !self.in_array && self.buf[self.pos..].trim_ascii().is_empty()
+    }
+}
+
+/// Length of the complete record (varint prefix plus message) at the start of `buf`, or
+/// `None` while more bytes are needed.
+fn delimited_record_len(buf: &[u8]) -> Result<Option<usize>> {
+    let mut message_len = 0usize;
+    for (i, byte) in buf.iter().take(10).enumerate() {
+        message_len |= usize::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            if message_len > MAX_PROTOBUF_RECORD_SIZE {
+                return Err(FlowError::Parse(format!(
+                    "Protobuf record of {} bytes exceeds the {} byte limit",
+                    message_len, MAX_PROTOBUF_RECORD_SIZE
+                )));
+            }
+            let record_len = i + 1 + message_len;
+            return Ok((buf.len() >= record_len).then_some(record_len));
+        }
+    }
+
+    if buf.len() >= 10 {
+        return Err(FlowError::Parse(
+            "Invalid protobuf length prefix".to_string(),
+        ));
+    }
+    Ok(None)
+}
+
+/// Reads `path` like `tail -f` (`FOLLOW`): once its end is reached, bytes appended later are
+/// read as they arrive. If it shrinks (truncated in place by a log rotator) it is read again
+/// from the start, and if another file takes its path (renamed away by one) the rest of the
+/// old file is read and the new one opened. A task pipes the bytes to the returned stream
+/// until that is dropped.
+fn follow(path: PathBuf, file: tokio::fs::File, limits: ReadLimits) -> ByteStream {
+    let (reader, mut writer) = tokio::io::duplex(limits.buffer_bytes);
+    tokio::spawn(async move {
+        match follow_file(&path, file, limits, &mut writer).await {
+            // The input was dropped, at shutdown
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+            Err(e) => error!("Stopped following {}: {}", path.display(), e),
+            Ok(()) => {}
+        }
+    });
+    Box::new(reader)
+}
+
+async fn follow_file(
+    path: &Path,
+    mut file: tokio::fs::File,
+    limits: ReadLimits,
+    writer: &mut DuplexStream,
+) -> io::Result<()> {
+    let mut buf = vec![0; limits.buffer_bytes];
+    let mut position = 0;
+    loop {
+        position += copy_to_end(&mut file, &mut buf, writer).await?;
+
+        let opened = file.metadata().await?;
+        if opened.len() < position {
+            info!(
+                "{} was truncated, reading it from the start",
+                path.display()
+            );
+            file.seek(SeekFrom::Start(0)).await?;
+            position = 0;
+            continue;
+        }
+
+        // Nothing at the path yet after a rename keeps the old file followed until there is
+        if let Ok(current) = tokio::fs::metadata(path).await
+            && file_id(&current) != file_id(&opened)
+        {
+            // The writer may have appended to the old file since it was read to its end
+            copy_to_end(&mut file, &mut buf, writer).await?;
+            file = tokio::fs::File::open(path).await?;
+            position = 0;
+            info!("{} was rotated, reopened it", path.display());
+            continue;
+        }
+        tokio::time::sleep(FOLLOW_POLL_INTERVAL).await;
+    }
+}
+
+/// Pipes what is left of `file` to `writer`, returning how many bytes that was.
+async fn copy_to_end(
+    file: &mut tokio::fs::File,
+    buf: &mut [u8],
+    writer: &mut DuplexStream,
+) -> io::Result<u64> {
+    let mut copied = 0;
+    loop {
+        let len = file.read(buf).await?;
+        if len == 0 {
+            return Ok(copied);
+        }
+        writer.write_all(&buf[..len]).await?;
+        copied += len as u64;
+    }
+}
+
+/// Device and inode of a file, to tell whether a path still names the file that was opened
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+/// Without inodes only truncation is detected
+#[cfg(not(unix))]
+fn file_id(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Expands a comma-separated list of paths and glob patterns, each pattern's matches sorted.
+fn expand_input_files(spec: &str) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in spec
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        if !entry.contains(['*', '?', '[']) {
+            paths.push(PathBuf::from(entry));
+            continue;
+        }
+
+        let mut matches = glob::glob(entry)
+            .map_err(|e| FlowError::Config(format!("Invalid input pattern '{}': {}", entry, e)))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(std::io::Error::from)?;
+        if matches.is_empty() {
+            return Err(FlowError::Config(format!(
+                "No input files match '{}'",
+                entry
+            )));
+        }
+        matches.sort();
+        paths.extend(matches);
+    }
+    Ok(paths)
+}
+
+/// Reads files in order, only keeping the current one open.
+pub struct FileSequence {
+    pending: VecDeque<PathBuf>,
+    current: Option<(String, RecordReader)>,
+    format: InputFormat,
+    limits: ReadLimits,
+    /// Decompress every file, not just those ending in `.gz`
+    gzip: bool,
+}
+
+impl FileSequence {
+    fn new(paths: Vec<PathBuf>, config: &Config) -> Self {
+        FileSequence {
+            pending: paths.into(),
+            current: None,
+            format: config.input_format,
+            limits: ReadLimits::new(config),
+            gzip: config.input_gzip,
+        }
+    }
+
+    async fn next_record(&mut self) -> Result<Option<Vec<u8>>> {
+        loop {
+            if let Some((_, reader)) = &mut self.current {
+                if let Some(record) = reader.next_record().await? {
+                    return Ok(Some(record));
+                }
+                self.current = None;
+            }
+
+            // Only dequeue once the file is open so a cancelled call doesn't skip it
+            let Some(path) = self.pending.front() else {
+                return Ok(None);
+            };
+            let file = tokio::fs::File::open(path).await.map_err(|e| {
+                FlowError::Config(format!("Failed to open {}: {}", path.display(), e))
+            })?;
+            let gzip = self.gzip || is_gzip_path(path);
+            let reader = RecordReader::new(Box::new(file), self.format, self.limits, gzip);
+            let name = path.display().to_string();
+            info!("Reading flows from {}", name);
+
+            self.pending.pop_front();
+            self.current = Some((name, reader));
+        }
+    }
+}
+
+async fn accept_connections(
+    listener: TcpListener,
+    format: InputFormat,
+    limits: ReadLimits,
+    tx: mpsc::Sender<Vec<u8>>,
+) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                info!("Accepted flow connection from {}", peer);
+                let reader = RecordReader::new(Box::new(stream), format, limits, false);
+                tokio::spawn(read_connection(reader, peer, tx.clone()));
+            }
+            Err(e) => {
+                // Usually transient, e.g. running out of file descriptors
+                warn!("Failed to accept TCP connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+async fn read_connection(mut reader: RecordReader, peer: SocketAddr, tx: mpsc::Sender<Vec<u8>>) {
+    loop {
+        match reader.next_record().await {
+            Ok(Some(record)) => {
+                if tx.send(record).await.is_err() {
+                    // The batching loop has shut down
+                    return;
+                }
+            }
+            Ok(None) => {
+                info!("Flow connection from {} closed", peer);
+                return;
+            }
+            Err(e) => {
+                warn!("Flow connection from {} failed: {}", peer, e);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+mod kafka {
+    use rdkafka::{
+        ClientConfig, Message, Offset, TopicPartitionList,
+        consumer::{CommitMode, Consumer, StreamConsumer},
+    };
+    use std::collections::{BTreeMap, VecDeque};
+    use tracing::{info, warn};
+
+    use crate::{
+        Config,
+        error::{FlowError, Result},
+    };
+
+    /// Kafka consumer with offsets committed explicitly, only once the records have been
+    /// written to InfluxDB (at-least-once delivery).
+    pub struct KafkaInput {
+        consumer: StreamConsumer,
+        topic: String,
+        /// (partition, offset) of each record returned but not yet acknowledged, oldest first
+        unacknowledged: VecDeque<(i32, i64)>,
+        returned: u64,
+    }
+
+    impl KafkaInput {
+        pub fn new(config: &Config) -> Result<Self> {
+            let consumer: StreamConsumer = ClientConfig::new()
+                .set("bootstrap.servers", &config.kafka_brokers)
+                .set("group.id", &config.kafka_group_id)
+                .set("enable.auto.commit", "false")
+                .set("auto.offset.reset", "earliest")
+                .create()
+                .map_err(|e| {
+                    FlowError::Config(format!("Failed to create Kafka consumer: {}", e))
+                })?;
+            consumer.subscribe(&[&config.kafka_topic]).map_err(|e| {
+                FlowError::Connectivity(format!(
+                    "Failed to subscribe to {}: {}",
+                    config.kafka_topic, e
+                ))
+            })?;
+
+            info!(
+                "Consuming flows from Kafka topic {} on {}",
+                config.kafka_topic, config.kafka_brokers
+            );
+            Ok(KafkaInput {
+                consumer,
+                topic: config.kafka_topic.clone(),
+                unacknowledged: VecDeque::new(),
+                returned: 0,
+            })
+        }
+
+        pub async fn next_record(&mut self) -> Result<Option<Vec<u8>>> {
+            loop {
+                match self.consumer.recv().await {
+                    Ok(message) => {
+                        self.unacknowledged
+                            .push_back((message.partition(), message.offset()));
+                        self.returned += 1;
+                        return Ok(Some(message.payload().unwrap_or_default().to_vec()));
+                    }
+                    // librdkafka reconnects on its own, so broker errors are only logged
+                    Err(e) => warn!("Kafka consumer error: {}", e),
+                }
+            }
+        }
+
+        /// Commits the offsets of the first `through` records returned.
+        pub fn commit(&mut self, through: u64) -> Result<()> {
+            let acknowledged = self.returned - self.unacknowledged.len() as u64;
+            let count = through.saturating_sub(acknowledged) as usize;
+            // Partitions are consumed in order, so the last offset per partition covers the rest
+            let offsets: BTreeMap<i32, i64> = self
+                .unacknowledged
+                .drain(..count.min(self.unacknowledged.len()))
+                .collect();
+            if offsets.is_empty() {
+                return Ok(());
+            }
+
+            let mut list = TopicPartitionList::new();
+            for (partition, offset) in offsets {
+                // The committed offset is the next one to consume
+                list.add_partition_offset(&self.topic, partition, Offset::Offset(offset + 1))
+                    .map_err(|e| FlowError::Connectivity(e.to_string()))?;
+            }
+            self.consumer.commit(&list, CommitMode::Async).map_err(|e| {
+                FlowError::Connectivity(format!("Failed to commit Kafka offsets: {}", e))
+            })
+        }
+    }
+}
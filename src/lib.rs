@@ -0,0 +1,2155 @@
+//! Parsing of GoFlow2 flow records and their conversion to InfluxDB points, plus the
+//! configuration and pipeline stages the `GoFlow2Influxdb` binary is built from.
+
+// The crate name is also the log target prefix (`GoFlow2Influxdb::pipeline`), so it stays as is
+#![allow(non_snake_case)]
+
+mod aggregate;
+pub mod cardinality;
+pub mod circuit;
+mod csv;
+pub mod dead_letter;
+mod dedup;
+pub mod enrich;
+pub mod error;
+pub mod health;
+pub mod input;
+pub mod metrics;
+pub mod pipeline;
+pub mod point;
+mod proto;
+pub mod queue;
+mod rollup;
+pub mod route;
+mod sequence;
+pub mod sink;
+pub mod spool;
+pub mod writer;
+
+use cardinality::{CardinalityLimiter, OverflowAction};
+use circuit::OpenPolicy;
+use csv::CsvHeader;
+use enrich::Enrichment;
+use error::{FlowError, Result};
+use input::{InputFormat, InputMode};
+use ipnet::IpNet;
+use point::Point;
+use queue::OverflowPolicy;
+use route::BucketRouter;
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    env,
+    net::IpAddr,
+    num::{NonZeroU32, NonZeroUsize},
+    path::Path,
+    str::FromStr,
+};
+use tracing_subscriber::EnvFilter;
+use writer::DryRunOutput;
+
+pub use writer::{RetryPolicy, write_batch_with_retry};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FlowData {
+    #[serde(rename = "type")]
+    pub flow_type: String,
+    #[serde(default = "defaults::time_received_ns")]
+    pub time_received_ns: u64,
+    #[serde(default = "defaults::sequence_num")]
+    pub sequence_num: u32,
+    #[serde(default = "defaults::sampling_rate")]
+    pub sampling_rate: u32,
+    pub sampler_address: String,
+    #[serde(default = "defaults::time_flow_start_ns")]
+    pub time_flow_start_ns: u64,
+    #[serde(default = "defaults::time_flow_end_ns")]
+    pub time_flow_end_ns: u64,
+    #[serde(default = "defaults::bytes")]
+    pub bytes: u64,
+    #[serde(default = "defaults::packets")]
+    pub packets: u64,
+    pub src_addr: String,
+    pub dst_addr: String,
+    pub etype: String,
+    pub proto: String,
+    #[serde(default = "defaults::src_port")]
+    pub src_port: u16,
+    #[serde(default = "defaults::dst_port")]
+    pub dst_port: u16,
+    #[serde(default = "defaults::in_if")]
+    pub in_if: u32,
+    #[serde(default = "defaults::out_if")]
+    pub out_if: u32,
+    pub src_mac: Option<String>,
+    pub dst_mac: Option<String>,
+    pub src_vlan: Option<u16>,
+    pub dst_vlan: Option<u16>,
+    pub vlan_id: Option<u16>,
+    pub ip_tos: Option<u8>,
+    pub forwarding_status: Option<u8>,
+    pub ip_ttl: Option<u8>,
+    pub ip_flags: Option<u16>,
+    pub tcp_flags: Option<u16>,
+    pub icmp_type: Option<u8>,
+    pub icmp_code: Option<u8>,
+    pub ipv6_flow_label: Option<u32>,
+    pub fragment_id: Option<u32>,
+    pub fragment_offset: Option<u32>,
+    pub src_as: Option<u32>,
+    pub dst_as: Option<u32>,
+    pub next_hop: Option<String>,
+    pub next_hop_as: Option<u32>,
+    pub src_net: Option<String>,
+    pub dst_net: Option<String>,
+    pub bgp_next_hop: Option<String>,
+    pub bgp_communities: Option<Vec<String>>,
+    pub as_path: Option<Vec<u32>>,
+    pub mpls_ttl: Option<Vec<u8>>,
+    pub mpls_label: Option<Vec<u32>>,
+    pub mpls_ip: Option<Vec<String>>,
+    pub observation_domain_id: Option<u32>,
+    pub observation_point_id: Option<u32>,
+    /// Flows found missing from the sampler's sequence numbers just before this one
+    /// (`SEQUENCE_GAP_TRACKING`); not part of the record
+    #[serde(skip)]
+    pub dropped_flows: u64,
+    /// Fields missing from the record and filled in by `defaults`; not part of the record
+    #[serde(skip)]
+    pub defaulted: Vec<&'static str>,
+}
+
+thread_local! {
+    /// Fields defaulted while decoding the current record, see `decode_json`
+    static DEFAULTED: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Defaults for the scalar fields some goflow2 configurations leave out, so such records
+/// are kept rather than failing to parse: all 0, with the receive time set to now in
+/// `TIMESTAMP_PRECISION` units by the pipeline. Each records its field in `DEFAULTED`.
+mod defaults {
+    fn record(field: &'static str) {
+        super::DEFAULTED.with_borrow_mut(|defaulted| {
+            if !defaulted.contains(&field) {
+                defaulted.push(field);
+            }
+        });
+    }
+
+    macro_rules! zero {
+        ($($field:ident: $ty:ty),* $(,)?) => {
+            $(
+                pub fn $field() -> $ty {
+                    record(stringify!($field));
+                    0
+                }
+            )*
+        };
+    }
+
+    zero!(
+        time_received_ns: u64,
+        sequence_num: u32,
+        sampling_rate: u32,
+        time_flow_start_ns: u64,
+        time_flow_end_ns: u64,
+        bytes: u64,
+        packets: u64,
+        src_port: u16,
+        dst_port: u16,
+        in_if: u32,
+        out_if: u32,
+    );
+}
+
+/// Which of a flow's addresses must fall in `filter_cidrs` for the flow to be kept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterDirection {
+    /// 只保留内网与外网之间的通讯，过滤掉内网间通讯和外网间通讯
+    Cross,
+    Src,
+    Dst,
+    Either,
+    Both,
+}
+
+impl FilterDirection {
+    fn keeps(self, src_matches: bool, dst_matches: bool) -> bool {
+        match self {
+            FilterDirection::Cross => src_matches != dst_matches,
+            FilterDirection::Src => src_matches,
+            FilterDirection::Dst => dst_matches,
+            FilterDirection::Either => src_matches || dst_matches,
+            FilterDirection::Both => src_matches && dst_matches,
+        }
+    }
+}
+
+impl FromStr for FilterDirection {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "cross" => Ok(FilterDirection::Cross),
+            "src" => Ok(FilterDirection::Src),
+            "dst" => Ok(FilterDirection::Dst),
+            "either" => Ok(FilterDirection::Either),
+            "both" => Ok(FilterDirection::Both),
+            other => Err(FlowError::Config(format!(
+                "Invalid FILTER_DIRECTION '{}', expected one of: cross, src, dst, either, both",
+                other
+            ))),
+        }
+    }
+}
+
+/// How log lines are written: the human-readable default, or one JSON object per line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(FlowError::Config(format!(
+                "Invalid LOG_FORMAT '{}', expected one of: text, json",
+                other
+            ))),
+        }
+    }
+}
+
+/// Which zero counters make a flow empty for `DROP_EMPTY_FLOWS`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmptyFlowCondition {
+    Bytes,
+    Packets,
+    Both,
+}
+
+impl EmptyFlowCondition {
+    fn matches(self, flow: &FlowData) -> bool {
+        match self {
+            EmptyFlowCondition::Bytes => flow.bytes == 0,
+            EmptyFlowCondition::Packets => flow.packets == 0,
+            EmptyFlowCondition::Both => flow.bytes == 0 && flow.packets == 0,
+        }
+    }
+}
+
+impl FromStr for EmptyFlowCondition {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "bytes" => Ok(EmptyFlowCondition::Bytes),
+            "packets" => Ok(EmptyFlowCondition::Packets),
+            "both" => Ok(EmptyFlowCondition::Both),
+            other => Err(FlowError::Config(format!(
+                "Invalid EMPTY_FLOW_CONDITION '{}', expected one of: bytes, packets, both",
+                other
+            ))),
+        }
+    }
+}
+
+/// Port ranges from `PORT_FILTER`, e.g. `0-1024,3389`, kept sorted and merged so a lookup
+/// is a binary search. Empty matches every port.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PortRanges(Vec<(u16, u16)>);
+
+impl PortRanges {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        let i = self.0.partition_point(|&(_, end)| end < port);
+        self.0.get(i).is_some_and(|&(start, _)| start <= port)
+    }
+}
+
+impl FromStr for PortRanges {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |entry: &str| {
+            FlowError::Config(format!(
+                "Invalid port range '{}' in PORT_FILTER, expected a port or start-end",
+                entry
+            ))
+        };
+
+        let mut ranges = Vec::new();
+        for entry in s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let (start, end) = entry.split_once('-').unwrap_or((entry, entry));
+            let start: u16 = start.trim().parse().map_err(|_| invalid(entry))?;
+            let end: u16 = end.trim().parse().map_err(|_| invalid(entry))?;
+            if start > end {
+                return Err(invalid(entry));
+            }
+            ranges.push((start, end));
+        }
+
+        ranges.sort_unstable();
+        let mut merged: Vec<(u16, u16)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        Ok(PortRanges(merged))
+    }
+}
+
+/// Which of a flow's ports must fall in `port_filter` for the flow to be kept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PortFilterSide {
+    Src,
+    Dst,
+    Either,
+}
+
+impl PortFilterSide {
+    fn keeps(self, ports: &PortRanges, flow: &FlowData) -> bool {
+        if ports.is_empty() {
+            return true;
+        }
+        match self {
+            PortFilterSide::Src => ports.contains(flow.src_port),
+            PortFilterSide::Dst => ports.contains(flow.dst_port),
+            PortFilterSide::Either => {
+                ports.contains(flow.src_port) || ports.contains(flow.dst_port)
+            }
+        }
+    }
+}
+
+impl FromStr for PortFilterSide {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "src" => Ok(PortFilterSide::Src),
+            "dst" => Ok(PortFilterSide::Dst),
+            "either" => Ok(PortFilterSide::Either),
+            other => Err(FlowError::Config(format!(
+                "Invalid PORT_FILTER_SIDE '{}', expected one of: src, dst, either",
+                other
+            ))),
+        }
+    }
+}
+
+/// What happens to a flow received further in the future than `MAX_FUTURE_SKEW_SECONDS`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FutureTimestampPolicy {
+    Drop,
+    /// Keep the flow, received now as far as the point is concerned
+    Clamp,
+}
+
+impl FromStr for FutureTimestampPolicy {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "drop" => Ok(FutureTimestampPolicy::Drop),
+            "clamp" => Ok(FutureTimestampPolicy::Clamp),
+            other => Err(FlowError::Config(format!(
+                "Invalid FUTURE_TIMESTAMP_POLICY '{}', expected one of: drop, clamp",
+                other
+            ))),
+        }
+    }
+}
+
+/// What happens to a flow whose `src_addr` or `dst_addr` isn't an IP address, which
+/// `FILTER_CIDRS` can't classify either way.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnparseableAddressPolicy {
+    /// Keep the flow without applying the address filter to it
+    Keep,
+    Drop,
+    /// Drop the flow, appending its record to `DEAD_LETTER_FILE`
+    DeadLetter,
+}
+
+impl FromStr for UnparseableAddressPolicy {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "keep" => Ok(UnparseableAddressPolicy::Keep),
+            "drop" => Ok(UnparseableAddressPolicy::Drop),
+            "dead_letter" => Ok(UnparseableAddressPolicy::DeadLetter),
+            other => Err(FlowError::Config(format!(
+                "Invalid UNPARSEABLE_ADDRESS_POLICY '{}', expected one of: keep, drop, dead_letter",
+                other
+            ))),
+        }
+    }
+}
+
+/// Every field `flow_to_datapoint` can write, and so the names `FIELD_ALLOWLIST` and
+/// `FIELD_DENYLIST` accept.
+pub const FIELD_NAMES: &[&str] = &[
+    "bytes",
+    "packets",
+    "src_port",
+    "dst_port",
+    "sequence_num",
+    "sampling_rate",
+    "time_flow_start_ns",
+    "time_flow_end_ns",
+    "time_received_ns",
+    "duration_ns",
+    "in_if",
+    "out_if",
+    "lag_ns",
+    "raw_bytes",
+    "raw_packets",
+    "dropped_flows",
+    "bps",
+    "pps",
+    "avg_pkt_size",
+    "src_vlan",
+    "dst_vlan",
+    "vlan_id",
+    "ip_tos",
+    "forwarding_status",
+    "ip_ttl",
+    "ip_flags",
+    "tcp_flags",
+    "ipv6_flow_label",
+    "fragment_id",
+    "fragment_offset",
+    "src_as",
+    "dst_as",
+    "src_as_org",
+    "dst_as_org",
+    "next_hop_as",
+    "next_hop",
+    "bgp_next_hop",
+    "observation_domain_id",
+    "observation_point_id",
+    "as_path_len",
+    "bgp_community_count",
+    "mpls_label_count",
+    "mpls_top_label",
+    "mpls_top_ttl",
+    "dscp",
+    "ecn",
+    "fwd_reason",
+    "tcp_fin",
+    "tcp_syn",
+    "tcp_rst",
+    "tcp_psh",
+    "tcp_ack",
+    "tcp_urg",
+];
+
+/// Which of the `FIELD_NAMES` fields are written. Other fields, such as tags the cardinality
+/// limit turned into fields, are always written.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum FieldFilter {
+    #[default]
+    All,
+    /// Only these (`FIELD_ALLOWLIST`)
+    Allow(HashSet<String>),
+    /// All but these (`FIELD_DENYLIST`)
+    Deny(HashSet<String>),
+}
+
+impl FieldFilter {
+    fn keeps(&self, name: &str) -> bool {
+        match self {
+            FieldFilter::All => true,
+            _ if !FIELD_NAMES.contains(&name) => true,
+            FieldFilter::Allow(names) => names.contains(name),
+            FieldFilter::Deny(names) => !names.contains(name),
+        }
+    }
+}
+
+/// Which of a flow's times becomes the point timestamp.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimestampSource {
+    Received,
+    FlowStart,
+    FlowEnd,
+}
+
+impl TimestampSource {
+    /// The chosen time, or the receive time when the exporter left it unset (0).
+    fn value(self, flow: &FlowData) -> u64 {
+        let value = match self {
+            TimestampSource::Received => flow.time_received_ns,
+            TimestampSource::FlowStart => flow.time_flow_start_ns,
+            TimestampSource::FlowEnd => flow.time_flow_end_ns,
+        };
+        if value == 0 {
+            flow.time_received_ns
+        } else {
+            value
+        }
+    }
+}
+
+impl FromStr for TimestampSource {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "received" => Ok(TimestampSource::Received),
+            "flow_start" => Ok(TimestampSource::FlowStart),
+            "flow_end" => Ok(TimestampSource::FlowEnd),
+            other => Err(FlowError::Config(format!(
+                "Invalid TIMESTAMP_SOURCE '{}', expected one of: received, flow_start, flow_end",
+                other
+            ))),
+        }
+    }
+}
+
+/// The unit exporters use for the `TIMESTAMP_SOURCE` time. Points are always written in
+/// nanoseconds, so other units are scaled up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimestampPrecision {
+    Nanoseconds,
+    Microseconds,
+    Milliseconds,
+    Seconds,
+}
+
+impl TimestampPrecision {
+    fn nanos_per_unit(self) -> u64 {
+        match self {
+            TimestampPrecision::Nanoseconds => 1,
+            TimestampPrecision::Microseconds => 1_000,
+            TimestampPrecision::Milliseconds => 1_000_000,
+            TimestampPrecision::Seconds => 1_000_000_000,
+        }
+    }
+
+    fn to_nanos(self, value: u64) -> i64 {
+        i64::try_from(value.saturating_mul(self.nanos_per_unit())).unwrap_or(i64::MAX)
+    }
+}
+
+impl FromStr for TimestampPrecision {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "ns" => Ok(TimestampPrecision::Nanoseconds),
+            "us" => Ok(TimestampPrecision::Microseconds),
+            "ms" => Ok(TimestampPrecision::Milliseconds),
+            "s" => Ok(TimestampPrecision::Seconds),
+            other => Err(FlowError::Config(format!(
+                "Invalid TIMESTAMP_PRECISION '{}', expected one of: ns, us, ms, s",
+                other
+            ))),
+        }
+    }
+}
+
+pub struct Config {
+    /// 1 writes to a database/retention policy, 2 to an org/bucket
+    pub influxdb_version: u8,
+    pub influxdb_url: String,
+    pub influxdb_token: String,
+    pub influxdb_org: String,
+    pub influxdb_bucket: String,
+    pub influxdb_database: String,
+    pub influxdb_retention_policy: Option<String>,
+    pub influxdb_username: Option<String>,
+    pub influxdb_password: Option<String>,
+    pub input_mode: InputMode,
+    pub goflow2_input_file: String,
+    pub input_format: InputFormat,
+    pub input_gzip: bool,
+    /// Keep reading `goflow2_input_file` past its end, like `tail -F`, across rotations
+    pub follow: bool,
+    /// Flush the pending batch once no record has been read for this long
+    pub input_idle_timeout_seconds: Option<u64>,
+    /// Stop reading, as at the end of the input, instead of only flushing when idle
+    pub input_idle_exit: bool,
+    pub strict_schema: bool,
+    /// Reject JSON flows with fields `FlowData` doesn't model, like `strict_schema` but
+    /// still defaulting missing ones
+    pub strict_fields: bool,
+    pub parse_error_log_limit: u32,
+    pub dead_letter_file: Option<String>,
+    /// Buffer size for reading text input
+    pub read_buffer_bytes: usize,
+    /// Longest line or JSON array element decoded; longer ones fail as parse errors
+    pub max_line_bytes: usize,
+    pub udp_listen_addr: String,
+    pub tcp_listen_addr: String,
+    #[cfg(feature = "kafka")]
+    pub kafka_brokers: String,
+    #[cfg(feature = "kafka")]
+    pub kafka_topic: String,
+    #[cfg(feature = "kafka")]
+    pub kafka_group_id: String,
+    pub batch_size: usize,
+    pub flush_interval_seconds: u64,
+    /// Records decoded in parallel groups when above 1, see `pipeline::read`
+    pub parse_workers: usize,
+    /// Records read ahead of the transform stage; `BATCH_SIZE` when unset
+    pub queue_capacity: Option<usize>,
+    pub overflow_policy: OverflowPolicy,
+    pub aggregate_5tuple: bool,
+    /// Write per-minute totals per sampler and protocol instead of a point per flow
+    pub rollup_enabled: bool,
+    pub inter_batch_delay_ms: u64,
+    pub write_workers: usize,
+    pub max_points_per_sec: Option<NonZeroU32>,
+    pub retry_attempts: u32,
+    pub retry_delay_ms: u64,
+    pub retry_max_delay_ms: u64,
+    pub retry_jitter: bool,
+    pub circuit_breaker_threshold: u32,
+    pub circuit_breaker_cooldown_ms: u64,
+    pub circuit_breaker_policy: OpenPolicy,
+    pub circuit_breaker_buffer_file: Option<String>,
+    /// Directory that failed batches are spooled to and replayed from
+    pub spool_dir: Option<String>,
+    pub spool_max_bytes: u64,
+    pub spool_replay_interval_seconds: u64,
+    pub dedup_window: usize,
+    pub sequence_gap_tracking: bool,
+    /// How far behind the highest sequence number a late flow may arrive
+    pub sequence_gap_tolerance: u32,
+    /// Flows received longer ago than this are dropped as stale
+    pub max_flow_age_seconds: Option<u64>,
+    /// How far ahead of our clock a flow's receive time may be
+    pub max_future_skew_seconds: Option<u64>,
+    pub future_timestamp_policy: FutureTimestampPolicy,
+    pub filter_cidrs: Vec<IpNet>,
+    pub filter_direction: FilterDirection,
+    pub filter_invert: bool,
+    pub unparseable_address_policy: UnparseableAddressPolicy,
+    pub drop_empty_flows: bool,
+    pub empty_flow_condition: EmptyFlowCondition,
+    pub min_bytes: u64,
+    /// Protocols to keep, by name where the number has one; empty keeps every protocol
+    pub proto_allowlist: Vec<String>,
+    pub port_filter: PortRanges,
+    pub port_filter_side: PortFilterSide,
+    /// AS numbers a flow's `src_as` or `dst_as` must be one of; empty keeps every flow
+    pub as_filter: HashSet<u32>,
+    /// Keep flows missing an AS number when no known one matches `as_filter`
+    pub as_filter_keep_unknown: bool,
+    pub measurement_name: String,
+    pub measurement_by_type: bool,
+    pub field_filter: FieldFilter,
+    /// Rules sending flows to buckets other than `influxdb_bucket`
+    pub bucket_routes: BucketRouter,
+    /// Tags written on every point, before the flow's own
+    pub static_tags: Vec<(String, String)>,
+    /// This machine's hostname, written as the `collector` tag; looked up once, at startup
+    pub collector_hostname: Option<String>,
+    pub timestamp_source: TimestampSource,
+    pub timestamp_precision: TimestampPrecision,
+    pub apply_sampling_scale: bool,
+    pub as_tags_enabled: bool,
+    pub as_path_tag_enabled: bool,
+    pub bgp_communities_tag_enabled: bool,
+    pub bgp_communities_separator: String,
+    pub vlan_tags_enabled: bool,
+    /// Write `next_hop`/`bgp_next_hop` as tags rather than fields
+    pub next_hop_tags_enabled: bool,
+    pub service_tags_enabled: bool,
+    pub src_service_tags_enabled: bool,
+    pub max_tag_cardinality: usize,
+    pub max_tag_cardinality_action: OverflowAction,
+    pub geoip_db_path: Option<String>,
+    pub metrics_addr: Option<String>,
+    pub health_addr: Option<String>,
+    /// Consecutive failed batch writes after which `/readyz` reports not ready
+    pub health_failure_threshold: u32,
+    pub asn_db_path: Option<String>,
+    pub ifindex_map: Option<String>,
+    pub sampler_names: Option<String>,
+    pub mac_vendor_enabled: bool,
+    /// OUI database read instead of the bundled table
+    pub mac_vendor_db: Option<String>,
+    pub reverse_dns_enabled: bool,
+    pub reverse_dns_cache_size: NonZeroUsize,
+    pub reverse_dns_concurrency: usize,
+    pub reverse_dns_timeout_ms: u64,
+    pub dry_run: bool,
+    pub dry_run_output: DryRunOutput,
+    pub log_format: LogFormat,
+    /// `RUST_LOG`-style filter directives, e.g. `info,GoFlow2Influxdb::pipeline=error`
+    pub log_level: String,
+}
+
+/// Written out rather than derived so the InfluxDB token and password never reach the logs.
+/// Every field is bound, so a new one can't be left out.
+impl std::fmt::Debug for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Config {
+            influxdb_version,
+            influxdb_url,
+            influxdb_token: _,
+            influxdb_org,
+            influxdb_bucket,
+            influxdb_database,
+            influxdb_retention_policy,
+            influxdb_username,
+            influxdb_password,
+            input_mode,
+            goflow2_input_file,
+            input_format,
+            input_gzip,
+            follow,
+            input_idle_timeout_seconds,
+            input_idle_exit,
+            strict_schema,
+            strict_fields,
+            parse_error_log_limit,
+            dead_letter_file,
+            read_buffer_bytes,
+            max_line_bytes,
+            udp_listen_addr,
+            tcp_listen_addr,
+            #[cfg(feature = "kafka")]
+            kafka_brokers,
+            #[cfg(feature = "kafka")]
+            kafka_topic,
+            #[cfg(feature = "kafka")]
+            kafka_group_id,
+            batch_size,
+            flush_interval_seconds,
+            parse_workers,
+            queue_capacity,
+            overflow_policy,
+            aggregate_5tuple,
+            rollup_enabled,
+            inter_batch_delay_ms,
+            write_workers,
+            max_points_per_sec,
+            retry_attempts,
+            retry_delay_ms,
+            retry_max_delay_ms,
+            retry_jitter,
+            circuit_breaker_threshold,
+            circuit_breaker_cooldown_ms,
+            circuit_breaker_policy,
+            circuit_breaker_buffer_file,
+            spool_dir,
+            spool_max_bytes,
+            spool_replay_interval_seconds,
+            dedup_window,
+            sequence_gap_tracking,
+            sequence_gap_tolerance,
+            max_flow_age_seconds,
+            max_future_skew_seconds,
+            future_timestamp_policy,
+            filter_cidrs,
+            filter_direction,
+            filter_invert,
+            unparseable_address_policy,
+            drop_empty_flows,
+            empty_flow_condition,
+            min_bytes,
+            proto_allowlist,
+            port_filter,
+            port_filter_side,
+            as_filter,
+            as_filter_keep_unknown,
+            measurement_name,
+            measurement_by_type,
+            field_filter,
+            bucket_routes,
+            static_tags,
+            collector_hostname,
+            timestamp_source,
+            timestamp_precision,
+            apply_sampling_scale,
+            as_tags_enabled,
+            as_path_tag_enabled,
+            bgp_communities_tag_enabled,
+            bgp_communities_separator,
+            vlan_tags_enabled,
+            next_hop_tags_enabled,
+            service_tags_enabled,
+            src_service_tags_enabled,
+            max_tag_cardinality,
+            max_tag_cardinality_action,
+            geoip_db_path,
+            metrics_addr,
+            health_addr,
+            health_failure_threshold,
+            asn_db_path,
+            ifindex_map,
+            sampler_names,
+            mac_vendor_enabled,
+            mac_vendor_db,
+            reverse_dns_enabled,
+            reverse_dns_cache_size,
+            reverse_dns_concurrency,
+            reverse_dns_timeout_ms,
+            dry_run,
+            dry_run_output,
+            log_format,
+            log_level,
+        } = self;
+
+        let mut debug = f.debug_struct("Config");
+        debug.field("influxdb_version", influxdb_version);
+        debug.field("influxdb_url", influxdb_url);
+        debug.field("influxdb_token", &"<redacted>");
+        debug.field("influxdb_org", influxdb_org);
+        debug.field("influxdb_bucket", influxdb_bucket);
+        debug.field("influxdb_database", influxdb_database);
+        debug.field("influxdb_retention_policy", influxdb_retention_policy);
+        debug.field("influxdb_username", influxdb_username);
+        debug.field(
+            "influxdb_password",
+            &influxdb_password.as_ref().map(|_| "<redacted>"),
+        );
+        debug.field("input_mode", input_mode);
+        debug.field("goflow2_input_file", goflow2_input_file);
+        debug.field("input_format", input_format);
+        debug.field("input_gzip", input_gzip);
+        debug.field("follow", follow);
+        debug.field("input_idle_timeout_seconds", input_idle_timeout_seconds);
+        debug.field("input_idle_exit", input_idle_exit);
+        debug.field("strict_schema", strict_schema);
+        debug.field("strict_fields", strict_fields);
+        debug.field("parse_error_log_limit", parse_error_log_limit);
+        debug.field("dead_letter_file", dead_letter_file);
+        debug.field("read_buffer_bytes", read_buffer_bytes);
+        debug.field("max_line_bytes", max_line_bytes);
+        debug.field("udp_listen_addr", udp_listen_addr);
+        debug.field("tcp_listen_addr", tcp_listen_addr);
+        #[cfg(feature = "kafka")]
+        debug.field("kafka_brokers", kafka_brokers);
+        #[cfg(feature = "kafka")]
+        debug.field("kafka_topic", kafka_topic);
+        #[cfg(feature = "kafka")]
+        debug.field("kafka_group_id", kafka_group_id);
+        debug.field("batch_size", batch_size);
+        debug.field("flush_interval_seconds", flush_interval_seconds);
+        debug.field("parse_workers", parse_workers);
+        debug.field("queue_capacity", queue_capacity);
+        debug.field("overflow_policy", overflow_policy);
+        debug.field("aggregate_5tuple", aggregate_5tuple);
+        debug.field("rollup_enabled", rollup_enabled);
+        debug.field("inter_batch_delay_ms", inter_batch_delay_ms);
+        debug.field("write_workers", write_workers);
+        debug.field("max_points_per_sec", max_points_per_sec);
+        debug.field("retry_attempts", retry_attempts);
+        debug.field("retry_delay_ms", retry_delay_ms);
+        debug.field("retry_max_delay_ms", retry_max_delay_ms);
+        debug.field("retry_jitter", retry_jitter);
+        debug.field("circuit_breaker_threshold", circuit_breaker_threshold);
+        debug.field("circuit_breaker_cooldown_ms", circuit_breaker_cooldown_ms);
+        debug.field("circuit_breaker_policy", circuit_breaker_policy);
+        debug.field("circuit_breaker_buffer_file", circuit_breaker_buffer_file);
+        debug.field("spool_dir", spool_dir);
+        debug.field("spool_max_bytes", spool_max_bytes);
+        debug.field(
+            "spool_replay_interval_seconds",
+            spool_replay_interval_seconds,
+        );
+        debug.field("dedup_window", dedup_window);
+        debug.field("sequence_gap_tracking", sequence_gap_tracking);
+        debug.field("sequence_gap_tolerance", sequence_gap_tolerance);
+        debug.field("max_flow_age_seconds", max_flow_age_seconds);
+        debug.field("max_future_skew_seconds", max_future_skew_seconds);
+        debug.field("future_timestamp_policy", future_timestamp_policy);
+        debug.field("filter_cidrs", filter_cidrs);
+        debug.field("filter_direction", filter_direction);
+        debug.field("filter_invert", filter_invert);
+        debug.field("unparseable_address_policy", unparseable_address_policy);
+        debug.field("drop_empty_flows", drop_empty_flows);
+        debug.field("empty_flow_condition", empty_flow_condition);
+        debug.field("min_bytes", min_bytes);
+        debug.field("proto_allowlist", proto_allowlist);
+        debug.field("port_filter", port_filter);
+        debug.field("port_filter_side", port_filter_side);
+        debug.field("as_filter", as_filter);
+        debug.field("as_filter_keep_unknown", as_filter_keep_unknown);
+        debug.field("measurement_name", measurement_name);
+        debug.field("measurement_by_type", measurement_by_type);
+        debug.field("field_filter", field_filter);
+        debug.field("bucket_routes", bucket_routes);
+        debug.field("static_tags", static_tags);
+        debug.field("collector_hostname", collector_hostname);
+        debug.field("timestamp_source", timestamp_source);
+        debug.field("timestamp_precision", timestamp_precision);
+        debug.field("apply_sampling_scale", apply_sampling_scale);
+        debug.field("as_tags_enabled", as_tags_enabled);
+        debug.field("as_path_tag_enabled", as_path_tag_enabled);
+        debug.field("bgp_communities_tag_enabled", bgp_communities_tag_enabled);
+        debug.field("bgp_communities_separator", bgp_communities_separator);
+        debug.field("vlan_tags_enabled", vlan_tags_enabled);
+        debug.field("next_hop_tags_enabled", next_hop_tags_enabled);
+        debug.field("service_tags_enabled", service_tags_enabled);
+        debug.field("src_service_tags_enabled", src_service_tags_enabled);
+        debug.field("max_tag_cardinality", max_tag_cardinality);
+        debug.field("max_tag_cardinality_action", max_tag_cardinality_action);
+        debug.field("geoip_db_path", geoip_db_path);
+        debug.field("metrics_addr", metrics_addr);
+        debug.field("health_addr", health_addr);
+        debug.field("health_failure_threshold", health_failure_threshold);
+        debug.field("asn_db_path", asn_db_path);
+        debug.field("ifindex_map", ifindex_map);
+        debug.field("sampler_names", sampler_names);
+        debug.field("mac_vendor_enabled", mac_vendor_enabled);
+        debug.field("mac_vendor_db", mac_vendor_db);
+        debug.field("reverse_dns_enabled", reverse_dns_enabled);
+        debug.field("reverse_dns_cache_size", reverse_dns_cache_size);
+        debug.field("reverse_dns_concurrency", reverse_dns_concurrency);
+        debug.field("reverse_dns_timeout_ms", reverse_dns_timeout_ms);
+        debug.field("dry_run", dry_run);
+        debug.field("dry_run_output", dry_run_output);
+        debug.field("log_format", log_format);
+        debug.field("log_level", log_level);
+        debug.finish()
+    }
+}
+
+impl Config {
+    /// Reads the config from environment variables only.
+    pub fn from_env() -> Result<Self> {
+        Self::from_lookup(|key| env::var(key).ok())
+    }
+
+    /// Reads the config from a TOML file only.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let table = read_config_file(path)?;
+        Self::from_lookup(|key| file_value(&table, key))
+    }
+
+    /// Merges all config sources. `overrides` (from the command line) take precedence over
+    /// environment variables, which take precedence over the config file.
+    pub fn from_sources(path: Option<&Path>, overrides: &HashMap<&str, String>) -> Result<Self> {
+        let table = path.map(read_config_file).transpose()?.unwrap_or_default();
+        Self::from_lookup(|key| {
+            overrides
+                .get(key)
+                .cloned()
+                .or_else(|| env::var(key).ok())
+                .or_else(|| file_value(&table, key))
+        })
+    }
+
+    /// Builds the config from `get`, which returns a setting's raw value by its env var name.
+    pub fn from_lookup(get: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let s = Settings::new(get);
+
+        let influxdb_version = s.parse("INFLUXDB_VERSION", "2", "1 or 2");
+        if !matches!(influxdb_version, 1 | 2) {
+            s.problem(format!(
+                "INFLUXDB_VERSION must be 1 or 2, got '{}'",
+                influxdb_version
+            ));
+        }
+        // Only the settings of the selected InfluxDB version are required
+        let required_for = |version: u8, key: &str| {
+            if influxdb_version == version {
+                s.required(key)
+            } else {
+                s.string(key, "")
+            }
+        };
+
+        let influxdb_url = s.required("INFLUXDB_URL");
+        if !influxdb_url.is_empty()
+            && !reqwest::Url::parse(&influxdb_url)
+                .is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+        {
+            s.problem(format!(
+                "INFLUXDB_URL must be an http(s) URL such as http://localhost:8086, got '{}'",
+                influxdb_url
+            ));
+        }
+
+        let config = Config {
+            influxdb_version,
+            influxdb_url,
+            influxdb_token: required_for(2, "INFLUXDB_TOKEN"),
+            influxdb_org: required_for(2, "INFLUXDB_ORG"),
+            influxdb_bucket: required_for(2, "INFLUXDB_BUCKET"),
+            influxdb_database: required_for(1, "INFLUXDB_DATABASE"),
+            influxdb_retention_policy: s.optional("INFLUXDB_RETENTION_POLICY"),
+            influxdb_username: s.optional("INFLUXDB_USERNAME"),
+            influxdb_password: s.optional("INFLUXDB_PASSWORD"),
+            input_mode: s.choice("INPUT_MODE", "file"),
+            goflow2_input_file: s.string("GOFLOW2_INPUT_FILE", "/dev/stdin"),
+            input_format: s.choice("INPUT_FORMAT", "json"),
+            input_gzip: s.flag("INPUT_GZIP", "false"),
+            follow: s.flag("FOLLOW", "false"),
+            input_idle_timeout_seconds: s
+                .optional("INPUT_IDLE_TIMEOUT_SECONDS")
+                .map(|_| s.positive("INPUT_IDLE_TIMEOUT_SECONDS", "1")),
+            input_idle_exit: s.flag("INPUT_IDLE_EXIT", "false"),
+            strict_schema: s.flag("STRICT_SCHEMA", "false"),
+            strict_fields: s.flag("STRICT_FIELDS", "false"),
+            parse_error_log_limit: s.number("PARSE_ERROR_LOG_LIMIT", "60"),
+            dead_letter_file: s.optional("DEAD_LETTER_FILE"),
+            read_buffer_bytes: s.positive("READ_BUFFER_BYTES", "8192"),
+            max_line_bytes: s.positive("MAX_LINE_BYTES", "1048576"),
+            udp_listen_addr: s.string("UDP_LISTEN_ADDR", "0.0.0.0:9995"),
+            tcp_listen_addr: s.string("TCP_LISTEN_ADDR", "0.0.0.0:9995"),
+            #[cfg(feature = "kafka")]
+            kafka_brokers: s.string("KAFKA_BROKERS", "localhost:9092"),
+            #[cfg(feature = "kafka")]
+            kafka_topic: s.string("KAFKA_TOPIC", "flows"),
+            #[cfg(feature = "kafka")]
+            kafka_group_id: s.string("KAFKA_GROUP_ID", "goflow2influxdb"),
+            batch_size: s.positive("BATCH_SIZE", "100"),
+            flush_interval_seconds: s.positive("FLUSH_INTERVAL_SECONDS", "10"),
+            parse_workers: s.positive("PARSE_WORKERS", "1"),
+            queue_capacity: s
+                .optional("QUEUE_CAPACITY")
+                .map(|_| s.positive("QUEUE_CAPACITY", "1")),
+            overflow_policy: s.choice("OVERFLOW_POLICY", "block"),
+            aggregate_5tuple: s.flag("AGGREGATE_5TUPLE", "false"),
+            rollup_enabled: s.flag("ROLLUP_ENABLED", "false"),
+            inter_batch_delay_ms: s.number("INTER_BATCH_DELAY_MS", "0"),
+            write_workers: s.positive("WRITE_WORKERS", "1"),
+            max_points_per_sec: s
+                .optional("MAX_POINTS_PER_SEC")
+                .filter(|value| !value.trim().is_empty())
+                .map(|_| s.parse("MAX_POINTS_PER_SEC", "1", "a positive integer")),
+            retry_attempts: s.positive("RETRY_ATTEMPTS", "3"),
+            retry_delay_ms: s.number("RETRY_DELAY_MS", "1000"),
+            retry_max_delay_ms: s.number("RETRY_MAX_DELAY_MS", "30000"),
+            retry_jitter: s.flag("RETRY_JITTER", "true"),
+            circuit_breaker_threshold: s.number("CIRCUIT_BREAKER_THRESHOLD", "5"),
+            circuit_breaker_cooldown_ms: s.number("CIRCUIT_BREAKER_COOLDOWN_MS", "30000"),
+            circuit_breaker_policy: s.choice("CIRCUIT_BREAKER_POLICY", "drop"),
+            circuit_breaker_buffer_file: s.optional("CIRCUIT_BREAKER_BUFFER_FILE"),
+            spool_dir: s.optional("SPOOL_DIR"),
+            spool_max_bytes: s.positive("SPOOL_MAX_BYTES", "1073741824"),
+            spool_replay_interval_seconds: s.positive("SPOOL_REPLAY_INTERVAL_SECONDS", "30"),
+            dedup_window: s.number("DEDUP_WINDOW", "0"),
+            sequence_gap_tracking: s.flag("SEQUENCE_GAP_TRACKING", "false"),
+            sequence_gap_tolerance: s.number("SEQUENCE_GAP_TOLERANCE", "16"),
+            max_flow_age_seconds: s
+                .optional("MAX_FLOW_AGE_SECONDS")
+                .map(|_| s.positive("MAX_FLOW_AGE_SECONDS", "1")),
+            max_future_skew_seconds: s
+                .optional("MAX_FUTURE_SKEW_SECONDS")
+                .map(|_| s.number("MAX_FUTURE_SKEW_SECONDS", "0")),
+            future_timestamp_policy: s.choice("FUTURE_TIMESTAMP_POLICY", "drop"),
+            filter_cidrs: match s.optional("FILTER_CIDRS") {
+                Some(value) if !value.trim().is_empty() => {
+                    parse_cidr_list(&value).unwrap_or_else(|e| {
+                        s.problem(e.to_string());
+                        Vec::new()
+                    })
+                }
+                _ => default_private_ranges(),
+            },
+            filter_direction: s.choice("FILTER_DIRECTION", "cross"),
+            filter_invert: s.flag("FILTER_INVERT", "false"),
+            unparseable_address_policy: s.choice("UNPARSEABLE_ADDRESS_POLICY", "keep"),
+            drop_empty_flows: s.flag("DROP_EMPTY_FLOWS", "false"),
+            empty_flow_condition: s.choice("EMPTY_FLOW_CONDITION", "both"),
+            min_bytes: s.number("MIN_BYTES", "0"),
+            proto_allowlist: s
+                .optional("PROTO_ALLOWLIST")
+                .map(|value| parse_proto_list(&value))
+                .unwrap_or_default(),
+            port_filter: s
+                .optional("PORT_FILTER")
+                .map(|value| {
+                    value.parse().unwrap_or_else(|e: FlowError| {
+                        s.problem(e.to_string());
+                        PortRanges::default()
+                    })
+                })
+                .unwrap_or_default(),
+            port_filter_side: s.choice("PORT_FILTER_SIDE", "either"),
+            as_filter: s
+                .optional("AS_FILTER")
+                .map(|value| {
+                    parse_as_list(&value).unwrap_or_else(|e| {
+                        s.problem(e.to_string());
+                        HashSet::new()
+                    })
+                })
+                .unwrap_or_default(),
+            as_filter_keep_unknown: s.flag("AS_FILTER_KEEP_UNKNOWN", "false"),
+            measurement_name: s.string("MEASUREMENT_NAME", "netflow"),
+            measurement_by_type: s.flag("MEASUREMENT_BY_TYPE", "false"),
+            field_filter: match (s.optional("FIELD_ALLOWLIST"), s.optional("FIELD_DENYLIST")) {
+                (Some(_), Some(_)) => {
+                    s.problem("FIELD_ALLOWLIST and FIELD_DENYLIST can't both be set".to_string());
+                    FieldFilter::All
+                }
+                (Some(value), None) => parse_field_list(&value, "FIELD_ALLOWLIST")
+                    .map(FieldFilter::Allow)
+                    .unwrap_or_else(|e| {
+                        s.problem(e.to_string());
+                        FieldFilter::All
+                    }),
+                (None, Some(value)) => parse_field_list(&value, "FIELD_DENYLIST")
+                    .map(FieldFilter::Deny)
+                    .unwrap_or_else(|e| {
+                        s.problem(e.to_string());
+                        FieldFilter::All
+                    }),
+                (None, None) => FieldFilter::All,
+            },
+            bucket_routes: s
+                .optional("BUCKET_ROUTES")
+                .map(|value| {
+                    value.parse().unwrap_or_else(|e: FlowError| {
+                        s.problem(e.to_string());
+                        BucketRouter::default()
+                    })
+                })
+                .unwrap_or_default(),
+            static_tags: s
+                .optional("STATIC_TAGS")
+                .map(|value| {
+                    parse_static_tags(&value).unwrap_or_else(|e| {
+                        s.problem(e.to_string());
+                        Vec::new()
+                    })
+                })
+                .unwrap_or_default(),
+            collector_hostname: s
+                .flag("HOSTNAME_TAG", "false")
+                .then(|| gethostname::gethostname().to_string_lossy().into_owned()),
+            timestamp_source: s.choice("TIMESTAMP_SOURCE", "received"),
+            timestamp_precision: s.choice("TIMESTAMP_PRECISION", "ns"),
+            apply_sampling_scale: s.flag("APPLY_SAMPLING_SCALE", "false"),
+            as_tags_enabled: s.flag("AS_TAGS_ENABLED", "false"),
+            as_path_tag_enabled: s.flag("AS_PATH_TAG_ENABLED", "false"),
+            bgp_communities_tag_enabled: s.flag("BGP_COMMUNITIES_TAG_ENABLED", "false"),
+            bgp_communities_separator: s.string("BGP_COMMUNITIES_SEPARATOR", ","),
+            vlan_tags_enabled: s.flag("VLAN_TAGS_ENABLED", "false"),
+            next_hop_tags_enabled: s.flag("NEXT_HOP_TAGS_ENABLED", "false"),
+            service_tags_enabled: s.flag("SERVICE_TAGS_ENABLED", "false"),
+            src_service_tags_enabled: s.flag("SRC_SERVICE_TAGS_ENABLED", "false"),
+            metrics_addr: s.optional("METRICS_ADDR"),
+            health_addr: s.optional("HEALTH_ADDR"),
+            health_failure_threshold: s.number("HEALTH_FAILURE_THRESHOLD", "3"),
+            max_tag_cardinality: s.number("MAX_TAG_CARDINALITY", "0"),
+            max_tag_cardinality_action: s.choice("MAX_TAG_CARDINALITY_ACTION", "field"),
+            geoip_db_path: s.optional("GEOIP_DB_PATH"),
+            asn_db_path: s.optional("ASN_DB_PATH"),
+            ifindex_map: s.optional("IFINDEX_MAP"),
+            sampler_names: s.optional("SAMPLER_NAMES"),
+            mac_vendor_enabled: s.flag("MAC_VENDOR_ENABLED", "false"),
+            mac_vendor_db: s.optional("MAC_VENDOR_DB"),
+            reverse_dns_enabled: s.flag("REVERSE_DNS_ENABLED", "false"),
+            reverse_dns_cache_size: s.parse(
+                "REVERSE_DNS_CACHE_SIZE",
+                "10000",
+                "a positive integer",
+            ),
+            reverse_dns_concurrency: s.positive("REVERSE_DNS_CONCURRENCY", "16"),
+            reverse_dns_timeout_ms: s.number("REVERSE_DNS_TIMEOUT_MS", "500"),
+            dry_run: s.flag("DRY_RUN", "false"),
+            dry_run_output: s.choice("DRY_RUN_OUTPUT", "count"),
+            log_format: s.choice("LOG_FORMAT", "text"),
+            log_level: s
+                .optional("LOG_LEVEL")
+                .or_else(|| s.optional("RUST_LOG"))
+                .unwrap_or_else(|| "info".to_string()),
+        };
+
+        // Each stream starts with its header, which records from UDP or merged TCP
+        // connections wouldn't have
+        if config.input_format == InputFormat::Csv && config.input_mode != InputMode::File {
+            s.problem("INPUT_FORMAT=csv requires INPUT_MODE=file".to_string());
+        }
+        if config.follow
+            && (config.input_mode != InputMode::File
+                || config.goflow2_input_file.contains([',', '*', '?', '[']))
+        {
+            s.problem("FOLLOW=true requires INPUT_MODE=file with a single input file".to_string());
+        }
+        if config.input_format == InputFormat::JsonArray
+            && !matches!(config.input_mode, InputMode::File | InputMode::Tcp)
+        {
+            s.problem("INPUT_FORMAT=json-array requires INPUT_MODE=file or tcp".to_string());
+        }
+
+        // Kafka offsets are committed past dropped records once later ones are written
+        if config.input_mode == InputMode::Kafka && config.overflow_policy != OverflowPolicy::Block
+        {
+            s.problem("INPUT_MODE=kafka requires OVERFLOW_POLICY=block".to_string());
+        }
+
+        if config.circuit_breaker_policy == OpenPolicy::Buffer
+            && config.circuit_breaker_buffer_file.is_none()
+        {
+            s.problem(
+                "CIRCUIT_BREAKER_POLICY=buffer requires CIRCUIT_BREAKER_BUFFER_FILE".to_string(),
+            );
+        }
+
+        if config.aggregate_5tuple && config.rollup_enabled {
+            s.problem("AGGREGATE_5TUPLE and ROLLUP_ENABLED can't both be set".to_string());
+        }
+
+        if let Err(e) = EnvFilter::try_new(&config.log_level) {
+            s.problem(format!(
+                "LOG_LEVEL must be a log filter such as 'info' or 'warn,GoFlow2Influxdb=debug', got '{}': {}",
+                config.log_level, e
+            ));
+        }
+
+        s.finish()?;
+        Ok(config)
+    }
+}
+
+/// Reads settings through `get`, collecting every invalid one so a misconfiguration is
+/// reported in full rather than one setting per run.
+///
+/// Invalid settings read as their default, so the `Config` being built stays usable until
+/// `finish` rejects it.
+struct Settings<G> {
+    get: G,
+    problems: RefCell<Vec<String>>,
+}
+
+impl<G: Fn(&str) -> Option<String>> Settings<G> {
+    fn new(get: G) -> Self {
+        Settings {
+            get,
+            problems: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn problem(&self, message: String) {
+        self.problems.borrow_mut().push(message);
+    }
+
+    fn string(&self, key: &str, default: &str) -> String {
+        (self.get)(key).unwrap_or_else(|| default.to_string())
+    }
+
+    /// The value of `key`, unless it's unset or empty.
+    fn optional(&self, key: &str) -> Option<String> {
+        (self.get)(key).filter(|value| !value.is_empty())
+    }
+
+    fn required(&self, key: &str) -> String {
+        self.optional(key).unwrap_or_else(|| {
+            self.problem(format!("{} is not set", key));
+            String::new()
+        })
+    }
+
+    /// Parses `key`, describing the value it should have been as `expected` when it's invalid.
+    fn parse<T: FromStr>(&self, key: &str, default: &str, expected: &str) -> T {
+        let value = self.string(key, default);
+        value.trim().parse().unwrap_or_else(|_| {
+            self.problem(format!("{} must be {}, got '{}'", key, expected, value));
+            Self::default_value(key, default)
+        })
+    }
+
+    fn number<T: FromStr>(&self, key: &str, default: &str) -> T {
+        self.parse(key, default, "a non-negative integer")
+    }
+
+    fn positive<T: FromStr + Default + PartialEq>(&self, key: &str, default: &str) -> T {
+        let value = self.parse(key, default, "a positive integer");
+        if value == T::default() {
+            self.problem(format!("{} must be a positive integer, got '0'", key));
+            return Self::default_value(key, default);
+        }
+        value
+    }
+
+    fn flag(&self, key: &str, default: &str) -> bool {
+        self.parse(key, default, "true or false")
+    }
+
+    /// Parses a setting whose `FromStr` error already says what was expected.
+    fn choice<T: FromStr<Err = FlowError>>(&self, key: &str, default: &str) -> T {
+        self.string(key, default)
+            .parse()
+            .unwrap_or_else(|e: FlowError| {
+                self.problem(e.to_string());
+                Self::default_value(key, default)
+            })
+    }
+
+    fn default_value<T: FromStr>(key: &str, default: &str) -> T {
+        match default.parse() {
+            Ok(value) => value,
+            Err(_) => unreachable!("invalid default '{}' for {}", default, key),
+        }
+    }
+
+    /// Fails with every problem found, one per line.
+    fn finish(self) -> Result<()> {
+        let problems = self.problems.into_inner();
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(FlowError::Config(format!(
+            "Invalid configuration:\n  {}",
+            problems.join("\n  ")
+        )))
+    }
+}
+
+fn read_config_file(path: &Path) -> Result<toml::Table> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        FlowError::Config(format!(
+            "Failed to read config file {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    toml::from_str(&contents).map_err(|e| {
+        FlowError::Config(format!(
+            "Failed to parse config file {}: {}",
+            path.display(),
+            e
+        ))
+    })
+}
+
+/// Looks up a setting by its env var name, e.g. `BATCH_SIZE` is read from `batch_size`.
+///
+/// Values are turned back into the strings the env var would hold, with arrays joined by
+/// commas so `filter_cidrs = ["10.0.0.0/8", "192.168.0.0/16"]` works as expected.
+fn file_value(table: &toml::Table, key: &str) -> Option<String> {
+    let to_string = |value: &toml::Value| match value {
+        toml::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+
+    match table.get(&key.to_ascii_lowercase())? {
+        toml::Value::Array(items) => {
+            Some(items.iter().map(to_string).collect::<Vec<_>>().join(","))
+        }
+        value => Some(to_string(value)),
+    }
+}
+
+pub fn default_private_ranges() -> Vec<IpNet> {
+    [
+        "10.0.0.0/8",
+        "172.16.0.0/12",
+        "192.168.0.0/16",
+        "100.64.0.0/10",
+        "169.254.0.0/16",
+        "127.0.0.0/8",
+        "fc00::/7",
+        "fe80::/10",
+        "::1/128",
+    ]
+    .iter()
+    .map(|cidr| IpNet::from_str(cidr).unwrap())
+    .collect()
+}
+
+fn parse_cidr_list(value: &str) -> Result<Vec<IpNet>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            IpNet::from_str(entry).map_err(|e| {
+                FlowError::Config(format!("Invalid CIDR '{}' in FILTER_CIDRS: {}", entry, e))
+            })
+        })
+        .collect()
+}
+
+/// Parses `key=value` pairs separated by commas, e.g. `datacenter=dc1,env=prod`.
+fn parse_static_tags(value: &str) -> Result<Vec<(String, String)>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() && !value.trim().is_empty() => {
+                Ok((key.trim().to_string(), value.trim().to_string()))
+            }
+            _ => Err(FlowError::Config(format!(
+                "Invalid tag '{}' in STATIC_TAGS, expected key=value",
+                entry
+            ))),
+        })
+        .collect()
+}
+
+/// Parses protocols separated by commas, e.g. `TCP,udp,1`. Numbers with a name are stored
+/// by name, so `6` and `tcp` match the same flows.
+fn parse_proto_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| proto_name(entry).to_string())
+        .collect()
+}
+
+/// Parses field names separated by commas, each of which must be one of `FIELD_NAMES`.
+fn parse_field_list(value: &str, setting: &str) -> Result<HashSet<String>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            if FIELD_NAMES.contains(&entry) {
+                Ok(entry.to_string())
+            } else {
+                Err(FlowError::Config(format!(
+                    "Unknown field '{}' in {}, expected one of: {}",
+                    entry,
+                    setting,
+                    FIELD_NAMES.join(", ")
+                )))
+            }
+        })
+        .collect()
+}
+
+/// Parses AS numbers separated by commas, with or without the `AS` prefix, e.g.
+/// `13335,AS15169`.
+fn parse_as_list(value: &str) -> Result<HashSet<u32>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let number = entry
+                .strip_prefix("AS")
+                .or_else(|| entry.strip_prefix("as"))
+                .unwrap_or(entry);
+            number.parse().map_err(|_| {
+                FlowError::Config(format!("Invalid AS number '{}' in AS_FILTER", entry))
+            })
+        })
+        .collect()
+}
+
+/// Whether `flow` passes `AS_FILTER`: either AS number is in the set or, with
+/// `AS_FILTER_KEEP_UNKNOWN`, one of them is missing and so might have been.
+fn as_allowed(flow: &FlowData, config: &Config) -> bool {
+    if config.as_filter.is_empty() {
+        return true;
+    }
+    let matches = |asn: Option<u32>| asn.is_some_and(|asn| config.as_filter.contains(&asn));
+    matches(flow.src_as)
+        || matches(flow.dst_as)
+        || (config.as_filter_keep_unknown && (flow.src_as.is_none() || flow.dst_as.is_none()))
+}
+
+/// Whether `proto`, a name or number, is in `allowlist` (see `parse_proto_list`). An empty
+/// list allows every protocol.
+fn proto_allowed(proto: &str, allowlist: &[String]) -> bool {
+    let proto = proto_name(proto);
+    allowlist.is_empty()
+        || allowlist
+            .iter()
+            .any(|entry| entry.eq_ignore_ascii_case(proto))
+}
+
+/// Where an address string falls relative to a set of private ranges.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AddressClass {
+    Private,
+    Public,
+    /// Not an IP address, e.g. empty for a non-IP frame
+    Unparseable,
+}
+
+pub fn classify_ip(ip_str: &str, private_ranges: &[IpNet]) -> AddressClass {
+    let Ok(ip) = IpAddr::from_str(ip_str) else {
+        return AddressClass::Unparseable;
+    };
+    // IPv4-mapped IPv6 addresses (::ffff:a.b.c.d) are checked as plain IPv4
+    let ip = ip.to_canonical();
+    if private_ranges.iter().any(|range| range.contains(&ip)) {
+        AddressClass::Private
+    } else {
+        AddressClass::Public
+    }
+}
+
+/// Whether `ip_str` is in `private_ranges`; false for anything that isn't an IP address,
+/// see `classify_ip` to tell those apart.
+pub fn is_private_ip(ip_str: &str, private_ranges: &[IpNet]) -> bool {
+    classify_ip(ip_str, private_ranges) == AddressClass::Private
+}
+
+/// Names a numeric `proto` such as "6"; names and unknown numbers are returned unchanged.
+fn proto_name(proto: &str) -> &str {
+    proto
+        .parse()
+        .ok()
+        .and_then(proto::protocol_name)
+        .unwrap_or(proto)
+}
+
+/// Names a known `etype`, written by name in any case (goflow2) or as a decimal or
+/// 0x-prefixed hex number; anything else is returned unchanged.
+fn etype_name(etype: &str) -> &str {
+    let number = match etype.get(..2) {
+        Some("0x" | "0X") => u32::from_str_radix(&etype[2..], 16).ok(),
+        _ => etype.parse().ok(),
+    };
+    number
+        .and_then(proto::ethertype_name)
+        .or_else(|| {
+            ["IPv4", "IPv6", "ARP"]
+                .into_iter()
+                .find(|name| name.eq_ignore_ascii_case(etype))
+        })
+        .unwrap_or(etype)
+}
+
+/// Whether `etype` is IPv6, written by name (goflow2) or as the number 0x86dd.
+fn is_ipv6_etype(etype: &str) -> bool {
+    etype_name(etype) == "IPv6"
+}
+
+/// Splits the IP ToS / traffic class byte into DSCP (top six bits) and ECN (low two bits).
+fn decode_tos(tos: u8) -> (u8, u8) {
+    (tos >> 2, tos & 0b11)
+}
+
+/// Splits an RFC 7270 forwarding status into its category (the top two bits) and reason
+/// code (the low six bits).
+fn decode_forwarding_status(status: u8) -> (&'static str, i64) {
+    let category = match status >> 6 {
+        0b01 => "forwarded",
+        0b10 => "dropped",
+        0b11 => "consumed",
+        _ => "unknown",
+    };
+    (category, i64::from(status & 0x3f))
+}
+
+/// Names common ICMP (or with `v6`, ICMPv6) type/code combinations.
+fn icmp_name(v6: bool, icmp_type: u8, icmp_code: u8) -> Option<&'static str> {
+    let name = match (v6, icmp_type, icmp_code) {
+        (false, 0, _) => "echo-reply",
+        (false, 3, 0) => "net-unreachable",
+        (false, 3, 1) => "host-unreachable",
+        (false, 3, 3) => "port-unreachable",
+        (false, 3, 4) => "fragmentation-needed",
+        (false, 3, 13) => "admin-prohibited",
+        (false, 3, _) => "dest-unreachable",
+        (false, 5, _) => "redirect",
+        (false, 8, _) => "echo-request",
+        (false, 11, _) => "time-exceeded",
+        (false, 12, _) => "parameter-problem",
+        (true, 1, 0) => "no-route",
+        (true, 1, 1) => "admin-prohibited",
+        (true, 1, 3) => "address-unreachable",
+        (true, 1, 4) => "port-unreachable",
+        (true, 1, _) => "dest-unreachable",
+        (true, 2, _) => "packet-too-big",
+        (true, 3, _) => "time-exceeded",
+        (true, 4, _) => "parameter-problem",
+        (true, 128, _) => "echo-request",
+        (true, 129, _) => "echo-reply",
+        (true, 133, _) => "router-solicitation",
+        (true, 134, _) => "router-advertisement",
+        (true, 135, _) => "neighbor-solicitation",
+        (true, 136, _) => "neighbor-advertisement",
+        (true, 137, _) => "redirect",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// The service usually found on a well-known port.
+fn port_service(port: u16) -> Option<&'static str> {
+    let service = match port {
+        20 => "ftp-data",
+        21 => "ftp",
+        22 => "ssh",
+        23 => "telnet",
+        25 => "smtp",
+        53 => "dns",
+        67 | 68 => "dhcp",
+        69 => "tftp",
+        80 => "http",
+        110 => "pop3",
+        123 => "ntp",
+        137..=139 => "netbios",
+        143 => "imap",
+        161 | 162 => "snmp",
+        179 => "bgp",
+        389 => "ldap",
+        443 => "https",
+        445 => "smb",
+        465 | 587 => "submission",
+        514 => "syslog",
+        636 => "ldaps",
+        853 => "dns-over-tls",
+        993 => "imaps",
+        995 => "pop3s",
+        1194 => "openvpn",
+        1433 => "mssql",
+        1812 | 1813 => "radius",
+        2055 => "netflow",
+        3306 => "mysql",
+        3389 => "rdp",
+        4739 => "ipfix",
+        5060 | 5061 => "sip",
+        5353 => "mdns",
+        5432 => "postgresql",
+        6343 => "sflow",
+        6379 => "redis",
+        8080 => "http-alt",
+        8443 => "https-alt",
+        _ => return None,
+    };
+    Some(service)
+}
+
+fn decode_tcp_flags(flags: u16) -> [(&'static str, i64); 6] {
+    let bit = |mask: u16| i64::from(flags & mask != 0);
+
+    [
+        ("tcp_fin", bit(0x01)),
+        ("tcp_syn", bit(0x02)),
+        ("tcp_rst", bit(0x04)),
+        ("tcp_psh", bit(0x08)),
+        ("tcp_ack", bit(0x10)),
+        ("tcp_urg", bit(0x20)),
+    ]
+}
+
+/// Decodes `record`; CSV rows by the `header` of the stream they were read from.
+fn decode_record(record: &[u8], header: Option<&CsvHeader>, config: &Config) -> Result<FlowData> {
+    match config.input_format {
+        InputFormat::Json | InputFormat::JsonArray
+            if config.strict_schema || config.strict_fields =>
+        {
+            decode_json_strict(record, config.strict_schema)
+        }
+        InputFormat::Json | InputFormat::JsonArray => decode_json(record),
+        InputFormat::Protobuf => proto::decode_flow(record),
+        InputFormat::Csv => match header {
+            Some(header) => with_defaults(|| csv::decode_row(header, record)),
+            None => Err(FlowError::Parse(
+                "CSV row read without a header".to_string(),
+            )),
+        },
+    }
+}
+
+/// Runs `decode`, listing the fields it defaulted in `FlowData::defaulted`.
+fn with_defaults(decode: impl FnOnce() -> Result<FlowData>) -> Result<FlowData> {
+    DEFAULTED.with_borrow_mut(Vec::clear);
+    let mut flow = decode()?;
+    flow.defaulted = DEFAULTED.take();
+    Ok(flow)
+}
+
+/// Decodes a JSON record, with `simd-json` when built with the `simd-json` feature. Missing
+/// scalar fields are defaulted and listed in `FlowData::defaulted`.
+pub fn decode_json(record: &[u8]) -> Result<FlowData> {
+    with_defaults(|| parse_json(record))
+}
+
+#[cfg(not(feature = "simd-json"))]
+fn parse_json(record: &[u8]) -> Result<FlowData> {
+    serde_json::from_slice(record).map_err(|e| FlowError::Parse(e.to_string()))
+}
+
+#[cfg(feature = "simd-json")]
+fn parse_json(record: &[u8]) -> Result<FlowData> {
+    thread_local! {
+        // simd-json parses in place, so records are copied into a buffer that is reused, as
+        // are its own scratch buffers
+        static BUFFERS: RefCell<(Vec<u8>, simd_json::Buffers)> =
+            RefCell::new((Vec::new(), simd_json::Buffers::default()));
+    }
+
+    BUFFERS.with_borrow_mut(|(input, buffers)| {
+        input.clear();
+        input.extend_from_slice(record);
+        simd_json::serde::from_slice_with_buffers(input, buffers)
+            .map_err(|e| FlowError::Parse(e.to_string()))
+    })
+}
+
+/// Decodes a JSON record, rejecting unknown fields and reporting wrong types as
+/// `FlowError::Schema` rather than a parse error. Missing fields are rejected too when
+/// `reject_missing` is set, and defaulted otherwise.
+fn decode_json_strict(record: &[u8], reject_missing: bool) -> Result<FlowData> {
+    DEFAULTED.with_borrow_mut(Vec::clear);
+    let mut unknown = Vec::new();
+    let mut deserializer = serde_json::Deserializer::from_slice(record);
+    let mut flow: FlowData =
+        serde_ignored::deserialize(&mut deserializer, |path| unknown.push(path.to_string()))
+            .and_then(|flow| deserializer.end().map(|()| flow))
+            .map_err(|e| match e.classify() {
+                serde_json::error::Category::Data => FlowError::Schema(e.to_string()),
+                _ => FlowError::Parse(e.to_string()),
+            })?;
+
+    if !unknown.is_empty() {
+        return Err(FlowError::Schema(format!(
+            "unknown fields: {}",
+            unknown.join(", ")
+        )));
+    }
+    let missing = DEFAULTED.take();
+    if reject_missing && !missing.is_empty() {
+        return Err(FlowError::Schema(format!(
+            "missing fields: {}",
+            missing.join(", ")
+        )));
+    }
+    flow.defaulted = missing;
+    Ok(flow)
+}
+
+/// `MEASUREMENT_NAME`, suffixed with the flow type (e.g. `netflow_SFLOW_5`) when
+/// `MEASUREMENT_BY_TYPE` is set.
+fn measurement_name<'a>(flow: &FlowData, config: &'a Config) -> Cow<'a, str> {
+    if !config.measurement_by_type {
+        return Cow::Borrowed(&config.measurement_name);
+    }
+
+    // Restrict the exporter-supplied type to characters that need no escaping
+    let flow_type: String = flow
+        .flow_type
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let flow_type = if flow_type.is_empty() {
+        "UNKNOWN"
+    } else {
+        &flow_type
+    };
+    Cow::Owned(format!("{}_{}", config.measurement_name, flow_type))
+}
+
+/// Time from the flow's start to its end, 0 for records whose end comes before their start
+/// (out of order, or from an exporter with a skewed clock).
+fn flow_duration_ns(flow: &FlowData, precision: TimestampPrecision) -> u64 {
+    let start = precision.to_nanos(flow.time_flow_start_ns);
+    let end = precision.to_nanos(flow.time_flow_end_ns);
+    end.saturating_sub(start).max(0) as u64
+}
+
+/// Builds the point for `flow`. `now_ns` is the time its batch was built, in nanoseconds
+/// since the UNIX epoch, from which the `lag_ns` field is computed.
+pub fn flow_to_datapoint(
+    flow: &FlowData,
+    measurement: &str,
+    config: &Config,
+    enrichment: &Enrichment,
+    tags: &CardinalityLimiter,
+    now_ns: u64,
+) -> Result<Point> {
+    let timestamp = config
+        .timestamp_precision
+        .to_nanos(config.timestamp_source.value(flow));
+
+    let (bytes, packets) = if config.apply_sampling_scale {
+        // A sampling_rate of 0 means the exporter did not sample, so scale by 1. The pipeline
+        // already writes it as 1, this covers other callers
+        let rate = flow.sampling_rate.max(1) as u64;
+        (
+            flow.bytes.saturating_mul(rate),
+            flow.packets.saturating_mul(rate),
+        )
+    } else {
+        (flow.bytes, flow.packets)
+    };
+
+    // Unsigned, so counters above i64::MAX aren't written as negative numbers
+    let mut builder = Point::builder(measurement)
+        .field("bytes", bytes)
+        .field("packets", packets)
+        .field("src_port", flow.src_port as i64)
+        .field("dst_port", flow.dst_port as i64)
+        .field("sequence_num", flow.sequence_num as i64)
+        .field("sampling_rate", flow.sampling_rate as i64)
+        .field("time_flow_start_ns", flow.time_flow_start_ns as i64)
+        .field("time_flow_end_ns", flow.time_flow_end_ns as i64)
+        .field("in_if", flow.in_if as i64)
+        .field("out_if", flow.out_if as i64);
+
+    // Kept alongside a flow start/end timestamp, so collector lag can be computed
+    if config.timestamp_source != TimestampSource::Received {
+        builder = builder.field("time_received_ns", flow.time_received_ns as i64);
+    }
+    // Clamped, as the clock of the host that received the flow may be ahead of ours
+    let received_ns = config.timestamp_precision.to_nanos(flow.time_received_ns) as u64;
+    builder = builder.field("lag_ns", now_ns.saturating_sub(received_ns) as i64);
+
+    if let Some(bucket) = config.bucket_routes.route(flow) {
+        builder = builder.bucket(bucket);
+    }
+
+    // Constant, so they bypass the cardinality limit; the flow's own tags win on a clash
+    for (key, value) in &config.static_tags {
+        builder = builder.tag(key, value);
+    }
+    if let Some(hostname) = &config.collector_hostname {
+        builder = builder.tag("collector", hostname);
+    }
+    for (key, value) in [
+        ("flow_type", flow.flow_type.as_str()),
+        ("src_addr", &flow.src_addr),
+        ("dst_addr", &flow.dst_addr),
+        ("proto", &flow.proto),
+        ("proto_name", proto_name(&flow.proto)),
+        ("etype", etype_name(&flow.etype)),
+        ("etype_raw", &flow.etype),
+        ("sampler_address", &flow.sampler_address),
+    ] {
+        builder = tags.tag(builder, key, value);
+    }
+
+    if config.apply_sampling_scale {
+        builder = builder
+            .field("raw_bytes", flow.bytes)
+            .field("raw_packets", flow.packets);
+    }
+
+    if flow.dropped_flows > 0 {
+        builder = builder.field("dropped_flows", flow.dropped_flows);
+    }
+
+    let duration_ns = flow_duration_ns(flow, config.timestamp_precision);
+    builder = builder.field("duration_ns", duration_ns as i64);
+    // Exporters that leave a time unset report 0, which gives no meaningful duration
+    if flow.time_flow_start_ns > 0 && duration_ns > 0 {
+        let seconds = duration_ns as f64 / 1e9;
+        builder = builder
+            .field("bps", bytes as f64 * 8.0 / seconds)
+            .field("pps", packets as f64 / seconds);
+    }
+    if flow.packets > 0 {
+        builder = builder.field("avg_pkt_size", flow.bytes as f64 / flow.packets as f64);
+    }
+
+    // Optional values are only written when the exporter provided them
+    let optional_fields = [
+        // VLAN IDs are written as tags instead when VLAN_TAGS_ENABLED is set
+        (
+            "src_vlan",
+            flow.src_vlan
+                .filter(|_| !config.vlan_tags_enabled)
+                .map(i64::from),
+        ),
+        (
+            "dst_vlan",
+            flow.dst_vlan
+                .filter(|_| !config.vlan_tags_enabled)
+                .map(i64::from),
+        ),
+        (
+            "vlan_id",
+            flow.vlan_id
+                .filter(|_| !config.vlan_tags_enabled)
+                .map(i64::from),
+        ),
+        ("ip_tos", flow.ip_tos.map(i64::from)),
+        ("forwarding_status", flow.forwarding_status.map(i64::from)),
+        ("ip_ttl", flow.ip_ttl.map(i64::from)),
+        ("ip_flags", flow.ip_flags.map(i64::from)),
+        ("tcp_flags", flow.tcp_flags.map(i64::from)),
+        // Exporters report a zero label for IPv4 flows, which carry none
+        (
+            "ipv6_flow_label",
+            flow.ipv6_flow_label
+                .filter(|_| is_ipv6_etype(&flow.etype))
+                .map(i64::from),
+        ),
+        ("fragment_id", flow.fragment_id.map(i64::from)),
+        ("fragment_offset", flow.fragment_offset.map(i64::from)),
+        // AS numbers are written as tags instead when AS_TAGS_ENABLED is set
+        (
+            "src_as",
+            flow.src_as
+                .filter(|_| !config.as_tags_enabled)
+                .map(i64::from),
+        ),
+        (
+            "dst_as",
+            flow.dst_as
+                .filter(|_| !config.as_tags_enabled)
+                .map(i64::from),
+        ),
+        ("next_hop_as", flow.next_hop_as.map(i64::from)),
+        (
+            "observation_domain_id",
+            flow.observation_domain_id.map(i64::from),
+        ),
+        (
+            "observation_point_id",
+            flow.observation_point_id.map(i64::from),
+        ),
+    ];
+
+    for (name, value) in optional_fields {
+        if let Some(v) = value {
+            builder = builder.field(name, v);
+        }
+    }
+
+    if config.as_tags_enabled {
+        if let Some(src_as) = flow.src_as {
+            builder = tags.tag(builder, "src_as", src_as.to_string());
+        }
+        if let Some(dst_as) = flow.dst_as {
+            builder = tags.tag(builder, "dst_as", dst_as.to_string());
+        }
+    }
+
+    if config.vlan_tags_enabled {
+        for (key, vlan) in [
+            ("vlan_id", flow.vlan_id),
+            ("src_vlan", flow.src_vlan),
+            ("dst_vlan", flow.dst_vlan),
+        ] {
+            if let Some(vlan) = vlan {
+                builder = tags.tag(builder, key, vlan.to_string());
+            }
+        }
+    }
+
+    // Exporters without routing data for the flow may send them empty, as sFlow agents do
+    for (key, next_hop) in [
+        ("next_hop", &flow.next_hop),
+        ("bgp_next_hop", &flow.bgp_next_hop),
+    ] {
+        if let Some(next_hop) = next_hop.as_deref().filter(|addr| !addr.is_empty()) {
+            builder = if config.next_hop_tags_enabled {
+                tags.tag(builder, key, next_hop)
+            } else {
+                builder.field(key, next_hop)
+            };
+        }
+    }
+
+    if let Some(as_path) = &flow.as_path {
+        builder = builder.field("as_path_len", as_path.len() as i64);
+
+        // Tag values can't be empty in line protocol, so an empty path only gets the length
+        if config.as_path_tag_enabled && !as_path.is_empty() {
+            let as_path_str = as_path
+                .iter()
+                .map(|asn| asn.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            builder = tags.tag(builder, "as_path_str", as_path_str);
+        }
+    }
+
+    if let Some(communities) = flow
+        .bgp_communities
+        .as_ref()
+        .filter(|communities| !communities.is_empty())
+    {
+        builder = builder.field("bgp_community_count", communities.len() as i64);
+        if config.bgp_communities_tag_enabled {
+            let joined = communities.join(&config.bgp_communities_separator);
+            builder = tags.tag(builder, "bgp_communities", joined);
+        }
+    }
+
+    // The top of the label stack is the label the flow was switched on
+    if let Some(labels) = flow.mpls_label.as_ref().filter(|labels| !labels.is_empty()) {
+        builder = builder
+            .field("mpls_label_count", labels.len() as i64)
+            .field("mpls_top_label", labels[0] as i64);
+        if let Some(ttl) = flow.mpls_ttl.as_ref().and_then(|ttls| ttls.first()) {
+            builder = builder.field("mpls_top_ttl", *ttl as i64);
+        }
+    }
+
+    if let Some(tos) = flow.ip_tos {
+        let (dscp, ecn) = decode_tos(tos);
+        builder = builder
+            .field("dscp", i64::from(dscp))
+            .field("ecn", i64::from(ecn));
+    }
+
+    if let Some(status) = flow.forwarding_status {
+        let (category, reason) = decode_forwarding_status(status);
+        builder = tags
+            .tag(builder, "fwd_status", category)
+            .field("fwd_reason", reason);
+    }
+
+    // Other protocols carry no ICMP header, so their type and code would only be zeros
+    let proto = proto_name(&flow.proto);
+    if proto == "ICMP" || proto == "ICMPv6" {
+        if let Some(icmp_type) = flow.icmp_type {
+            builder = tags.tag(builder, "icmp_type", icmp_type.to_string());
+        }
+        if let Some(icmp_code) = flow.icmp_code {
+            builder = tags.tag(builder, "icmp_code", icmp_code.to_string());
+        }
+        if let Some(name) = flow.icmp_type.and_then(|icmp_type| {
+            icmp_name(proto == "ICMPv6", icmp_type, flow.icmp_code.unwrap_or(0))
+        }) {
+            builder = tags.tag(builder, "icmp_name", name);
+        }
+    }
+
+    // Ephemeral ports get no tag, which keeps the tags' cardinality small
+    if config.service_tags_enabled
+        && let Some(service) = port_service(flow.dst_port)
+    {
+        builder = tags.tag(builder, "dst_service", service);
+    }
+    if config.src_service_tags_enabled
+        && let Some(service) = port_service(flow.src_port)
+    {
+        builder = tags.tag(builder, "src_service", service);
+    }
+
+    if let Some(flags) = flow.tcp_flags {
+        for (name, set) in decode_tcp_flags(flags) {
+            builder = builder.field(name, set);
+        }
+    }
+
+    enrichment
+        .apply(builder, flow, tags)
+        .retain_fields(|name| config.field_filter.keeps(name))
+        .timestamp(timestamp)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        Config::from_lookup(|key| match key {
+            "INFLUXDB_URL" => Some("http://localhost:8086".to_string()),
+            "INFLUXDB_TOKEN" | "INFLUXDB_ORG" | "INFLUXDB_BUCKET" => Some("test".to_string()),
+            _ => None,
+        })
+        .unwrap()
+    }
+
+    /// An IPFIX flow with every required field, `overrides` (a JSON object) replacing some.
+    fn flow(overrides: serde_json::Value) -> FlowData {
+        let mut record = serde_json::json!({
+            "type": "IPFIX",
+            "time_received_ns": 1,
+            "sequence_num": 1,
+            "sampling_rate": 0,
+            "sampler_address": "192.0.2.1",
+            "time_flow_start_ns": 1,
+            "time_flow_end_ns": 2,
+            "bytes": 1,
+            "packets": 1,
+            "src_addr": "192.0.2.10",
+            "dst_addr": "198.51.100.20",
+            "etype": "IPv4",
+            "proto": "TCP",
+            "src_port": 1234,
+            "dst_port": 443,
+            "in_if": 1,
+            "out_if": 2,
+        });
+        if let (Some(record), serde_json::Value::Object(overrides)) =
+            (record.as_object_mut(), overrides)
+        {
+            record.extend(overrides);
+        }
+        serde_json::from_value(record).unwrap()
+    }
+
+    /// `flow` as line protocol, written with the default settings.
+    fn line(flow: &FlowData) -> String {
+        use influxdb2::models::WriteDataPoint;
+
+        let config = config();
+        let enrichment = Enrichment::from_config(&config).unwrap();
+        let tags = CardinalityLimiter::new(0, OverflowAction::Field);
+        let point = flow_to_datapoint(flow, "netflow", &config, &enrichment, &tags, 0).unwrap();
+        let mut line = Vec::new();
+        point.write_data_point_to(&mut line).unwrap();
+        String::from_utf8(line).unwrap()
+    }
+
+    #[test]
+    fn classifies_cgnat_link_local_and_loopback_as_private() {
+        let ranges = default_private_ranges();
+
+        assert!(is_private_ip("100.64.0.1", &ranges));
+        assert!(is_private_ip("100.127.255.254", &ranges));
+        assert!(is_private_ip("169.254.10.20", &ranges));
+        assert!(is_private_ip("127.0.0.1", &ranges));
+        assert!(!is_private_ip("100.128.0.1", &ranges));
+        assert!(!is_private_ip("8.8.8.8", &ranges));
+    }
+
+    #[test]
+    fn splits_tos_into_dscp_and_ecn() {
+        assert_eq!(decode_tos(0), (0, 0));
+        // EF with ECT(0)
+        assert_eq!(decode_tos(0xb8 | 0b10), (46, 2));
+        // AF41 with CE
+        assert_eq!(decode_tos(0x88 | 0b11), (34, 3));
+        assert_eq!(decode_tos(0xff), (63, 3));
+    }
+
+    #[test]
+    fn writes_counters_above_i64_max_as_unsigned() {
+        let bytes = i64::MAX as u64 + 1;
+        let line = line(&flow(
+            serde_json::json!({"bytes": bytes, "packets": u64::MAX}),
+        ));
+
+        let field = |name: &str| -> u64 {
+            let prefix = format!("{}=", name);
+            let value = line
+                .split([' ', ','])
+                .find_map(|part| part.strip_prefix(prefix.as_str()))
+                .unwrap();
+            value.strip_suffix('u').unwrap().parse().unwrap()
+        };
+        assert_eq!(field("bytes"), bytes);
+        assert_eq!(field("packets"), u64::MAX);
+    }
+
+    #[test]
+    fn clamps_negative_flow_durations_to_zero() {
+        let flow = |start: u64, end: u64| {
+            flow(serde_json::json!({"time_flow_start_ns": start, "time_flow_end_ns": end}))
+        };
+        let ns = TimestampPrecision::Nanoseconds;
+
+        assert_eq!(flow_duration_ns(&flow(100, 350), ns), 250);
+        assert_eq!(flow_duration_ns(&flow(350, 100), ns), 0);
+        assert_eq!(flow_duration_ns(&flow(7, 7), ns), 0);
+        assert_eq!(flow_duration_ns(&flow(0, u64::MAX), ns), i64::MAX as u64);
+        assert_eq!(
+            flow_duration_ns(&flow(2, 5), TimestampPrecision::Seconds),
+            3_000_000_000
+        );
+    }
+}
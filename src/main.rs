@@ -1,265 +1,249 @@
-use anyhow::Result;
+use GoFlow2Influxdb::{
+    Config, LogFormat,
+    cardinality::CardinalityLimiter,
+    circuit::CircuitBreaker,
+    dead_letter::DeadLetter,
+    enrich::Enrichment,
+    health,
+    input::Input,
+    metrics::{self, METRICS},
+    pipeline, queue, sink,
+    spool::{self, Spool},
+    writer::Writer,
+};
+use clap::Parser;
 use dotenv::dotenv;
-use futures::stream;
-use influxdb2::{Client, models::DataPoint};
-use ipnet::Ipv4Net;
-use serde::{Deserialize, Serialize};
-use std::{env, net::Ipv4Addr, str::FromStr, time::Duration};
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::time::sleep;
-use tracing::{error, info, warn};
-
-#[derive(Debug, Deserialize, Serialize)]
-struct FlowData {
-    #[serde(rename = "type")]
-    flow_type: String,
-    time_received_ns: u64,
-    sequence_num: u32,
-    sampling_rate: u32,
-    sampler_address: String,
-    time_flow_start_ns: u64,
-    time_flow_end_ns: u64,
-    bytes: u64,
-    packets: u64,
-    src_addr: String,
-    dst_addr: String,
-    etype: String,
-    proto: String,
-    src_port: u16,
-    dst_port: u16,
-    in_if: u32,
-    out_if: u32,
-    src_mac: Option<String>,
-    dst_mac: Option<String>,
-    src_vlan: Option<u16>,
-    dst_vlan: Option<u16>,
-    vlan_id: Option<u16>,
-    ip_tos: Option<u8>,
-    forwarding_status: Option<u8>,
-    ip_ttl: Option<u8>,
-    ip_flags: Option<u16>,
-    tcp_flags: Option<u16>,
-    icmp_type: Option<u8>,
-    icmp_code: Option<u8>,
-    ipv6_flow_label: Option<u32>,
-    fragment_id: Option<u32>,
-    fragment_offset: Option<u32>,
-    src_as: Option<u32>,
-    dst_as: Option<u32>,
-    next_hop: Option<String>,
-    next_hop_as: Option<u32>,
-    src_net: Option<String>,
-    dst_net: Option<String>,
-    bgp_next_hop: Option<String>,
-    bgp_communities: Option<Vec<String>>,
-    as_path: Option<Vec<u32>>,
-    mpls_ttl: Option<Vec<u8>>,
-    mpls_label: Option<Vec<u32>>,
-    mpls_ip: Option<Vec<String>>,
-    observation_domain_id: Option<u32>,
-    observation_point_id: Option<u32>,
-}
-
-#[derive(Debug)]
-struct Config {
-    influxdb_url: String,
-    influxdb_token: String,
-    influxdb_org: String,
-    influxdb_bucket: String,
-    goflow2_input_file: String,
-    batch_size: usize,
-    flush_interval_seconds: u64,
-    retry_attempts: u32,
-    retry_delay_ms: u64,
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
+use tokio::sync::{mpsc, watch};
+use tracing::{info, warn};
+use tracing_subscriber::EnvFilter;
+
+/// Command line arguments. Anything set here overrides the environment and config file.
+#[derive(Debug, Parser)]
+#[command(version, about = "Sync GoFlow2 network flow data to InfluxDB")]
+struct Args {
+    /// TOML config file; keys are the lowercase env var names
+    #[arg(long, env = "CONFIG_FILE")]
+    config: Option<PathBuf>,
+
+    /// File to read GoFlow2 JSON from (GOFLOW2_INPUT_FILE)
+    #[arg(long)]
+    input: Option<String>,
+
+    /// InfluxDB bucket to write to (INFLUXDB_BUCKET)
+    #[arg(long)]
+    bucket: Option<String>,
+
+    /// Number of points per write (BATCH_SIZE)
+    #[arg(long)]
+    batch_size: Option<usize>,
+
+    /// Run the whole pipeline but don't write to InfluxDB (DRY_RUN)
+    #[arg(long)]
+    dry_run: bool,
 }
 
-impl Config {
-    fn from_env() -> Result<Self> {
-        Ok(Config {
-            influxdb_url: env::var("INFLUXDB_URL")?,
-            influxdb_token: env::var("INFLUXDB_TOKEN")?,
-            influxdb_org: env::var("INFLUXDB_ORG")?,
-            influxdb_bucket: env::var("INFLUXDB_BUCKET")?,
-            goflow2_input_file: env::var("GOFLOW2_INPUT_FILE")
-                .unwrap_or_else(|_| "/dev/stdin".to_string()),
-            batch_size: env::var("BATCH_SIZE")
-                .unwrap_or_else(|_| "100".to_string())
-                .parse()?,
-            flush_interval_seconds: env::var("FLUSH_INTERVAL_SECONDS")
-                .unwrap_or_else(|_| "10".to_string())
-                .parse()?,
-            retry_attempts: env::var("RETRY_ATTEMPTS")
-                .unwrap_or_else(|_| "3".to_string())
-                .parse()?,
-            retry_delay_ms: env::var("RETRY_DELAY_MS")
-                .unwrap_or_else(|_| "1000".to_string())
-                .parse()?,
-        })
+impl Args {
+    /// The settings given on the command line, keyed by the env var they override.
+    fn overrides(&self) -> HashMap<&'static str, String> {
+        let mut overrides = HashMap::new();
+        if let Some(input) = &self.input {
+            overrides.insert("GOFLOW2_INPUT_FILE", input.clone());
+        }
+        if let Some(bucket) = &self.bucket {
+            overrides.insert("INFLUXDB_BUCKET", bucket.clone());
+        }
+        if let Some(batch_size) = self.batch_size {
+            overrides.insert("BATCH_SIZE", batch_size.to_string());
+        }
+        if self.dry_run {
+            overrides.insert("DRY_RUN", "true".to_string());
+        }
+        overrides
     }
 }
 
-fn is_private_ip(ip_str: &str) -> bool {
-    if let Ok(ip) = Ipv4Addr::from_str(ip_str) {
-        let private_ranges = [
-            Ipv4Net::from_str("10.0.0.0/8").unwrap(),
-            Ipv4Net::from_str("172.16.0.0/12").unwrap(),
-            Ipv4Net::from_str("192.168.0.0/16").unwrap(),
-        ];
-
-        private_ranges.iter().any(|range| range.contains(&ip))
-    } else {
-        false
-    }
-}
+/// Resolves on the first SIGINT (Ctrl-C) or SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
 
-async fn write_batch_with_retry(
-    client: &Client,
-    bucket: &str,
-    batch: Vec<DataPoint>,
-    retry_attempts: u32,
-    retry_delay_ms: u64,
-) -> Result<()> {
-    for attempt in 1..=retry_attempts {
-        match client.write(bucket, stream::iter(batch.clone())).await {
-            Ok(_) => {
-                info!("Successfully wrote batch of {} points to InfluxDB", batch.len());
-                return Ok(());
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
             }
             Err(e) => {
-                if attempt == retry_attempts {
-                    return Err(anyhow::anyhow!("Failed to write batch after {} attempts: {}", retry_attempts, e));
-                }
-                warn!("Attempt {}/{} failed: {}. Retrying in {}ms...", attempt, retry_attempts, e, retry_delay_ms);
-                sleep(Duration::from_millis(retry_delay_ms)).await;
+                warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
             }
         }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
     }
-    unreachable!()
 }
 
-fn flow_to_datapoint(flow: &FlowData) -> DataPoint {
-    let timestamp = flow.time_received_ns as i64;
+fn main() -> anyhow::Result<()> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    let result = runtime.block_on(run());
 
-    DataPoint::builder("netflow")
-        .tag("flow_type", &flow.flow_type)
-        .tag("src_addr", &flow.src_addr)
-        .tag("dst_addr", &flow.dst_addr)
-        .tag("proto", &flow.proto)
-        .tag("sampler_address", &flow.sampler_address)
-        .field("bytes", flow.bytes as i64)
-        .field("packets", flow.packets as i64)
-        .field("src_port", flow.src_port as i64)
-        .field("dst_port", flow.dst_port as i64)
-        .field("sequence_num", flow.sequence_num as i64)
-        .field("sampling_rate", flow.sampling_rate as i64)
-        .field("time_flow_start_ns", flow.time_flow_start_ns as i64)
-        .field("time_flow_end_ns", flow.time_flow_end_ns as i64)
-        .field("in_if", flow.in_if as i64)
-        .field("out_if", flow.out_if as i64)
-        .timestamp(timestamp)
-        .build()
-        .expect("Failed to build DataPoint")
+    // Reads from stdin run on a blocking thread that can't be cancelled, so don't wait for it
+    // when shutting down on a signal
+    runtime.shutdown_background();
+    result
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+async fn run() -> anyhow::Result<()> {
     dotenv().ok();
 
-    tracing_subscriber::fmt::init();
-
-    let config = Config::from_env()?;
+    let args = Args::parse();
+    let config = Arc::new(Config::from_sources(
+        args.config.as_deref(),
+        &args.overrides(),
+    )?);
+
+    // Nothing is logged before the config is loaded; its errors are returned from main
+    let filter = EnvFilter::try_new(&config.log_level)?;
+    match config.log_format {
+        LogFormat::Text => tracing_subscriber::fmt().with_env_filter(filter).init(),
+        LogFormat::Json => tracing_subscriber::fmt()
+            .json()
+            .with_env_filter(filter)
+            .init(),
+    }
     info!("Starting GoFlow2Influxdb with config: {:?}", config);
 
-    let client = Client::new(
-        &config.influxdb_url,
-        &config.influxdb_org,
-        &config.influxdb_token,
+    let enrichment = Enrichment::from_config(&config)?;
+    let tag_limiter = CardinalityLimiter::new(
+        config.max_tag_cardinality,
+        config.max_tag_cardinality_action,
     );
 
-    let input: Box<dyn tokio::io::AsyncRead + Unpin> = if config.goflow2_input_file == "/dev/stdin"
-    {
-        Box::new(tokio::io::stdin())
-    } else {
-        Box::new(tokio::fs::File::open(&config.goflow2_input_file).await?)
-    };
+    let sink = sink::from_config(&config);
+    let breaker = Arc::new(CircuitBreaker::from_config(&config));
 
-    let reader = BufReader::new(input);
-    let mut lines = reader.lines();
-    let mut batch = Vec::new();
-    let mut total_processed = 0u64;
-    let mut filtered_out = 0u64;
-
-    info!("Starting to process flow data...");
+    if let Some(addr) = &config.metrics_addr {
+        metrics::serve(addr).await?;
+    }
+    if let Some(addr) = &config.health_addr {
+        health::serve(addr, sink.clone(), config.health_failure_threshold).await?;
+    }
 
-    while let Some(line) = lines.next_line().await? {
-        if line.trim().is_empty() {
-            continue;
+    let input = Input::open(&config).await?;
+    let dead_letter = match &config.dead_letter_file {
+        Some(path) => Some(DeadLetter::open(path, config.input_format).await?),
+        None => None,
+    };
+    // Nothing fails to write in a dry run, and replaying would write for real
+    let spool = match &config.spool_dir {
+        Some(dir) if !config.dry_run => {
+            let spool = Arc::new(Spool::open(dir, config.spool_max_bytes).await?);
+            // What an earlier run spooled goes first, before new batches can be spooled
+            spool.try_replay(&*sink).await;
+            spool::spawn_replay(
+                spool.clone(),
+                sink.clone(),
+                Duration::from_secs(config.spool_replay_interval_seconds),
+            );
+            Some(spool)
         }
+        _ => None,
+    };
+    let writer = Writer::new(
+        sink,
+        breaker,
+        config.clone(),
+        input.tracks_delivery(),
+        spool,
+    );
 
-        match serde_json::from_str::<FlowData>(&line) {
-            Ok(flow) => {
-                total_processed += 1;
-
-                let src_is_private = is_private_ip(&flow.src_addr);
-                let dst_is_private = is_private_ip(&flow.dst_addr);
-                
-                // 只保留内网与外网之间的通讯，过滤掉内网间通讯和外网间通讯
-                if (src_is_private && dst_is_private) || (!src_is_private && !dst_is_private) {
-                    filtered_out += 1;
-                    continue;
-                }
-
-                let datapoint = flow_to_datapoint(&flow);
-                batch.push(datapoint);
-
-                if batch.len() >= config.batch_size {
-                    let batch_to_write: Vec<_> = batch.drain(..).collect();
-
-                    if let Err(e) = write_batch_with_retry(
-                        &client,
-                        &config.influxdb_bucket,
-                        batch_to_write,
-                        config.retry_attempts,
-                        config.retry_delay_ms,
-                    ).await {
-                        error!("Failed to write batch to InfluxDB: {}", e);
-                    }
+    let (shutdown_tx, shutdown) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        let _ = shutdown_tx.send(true);
+    });
 
-                    // Add delay between batch writes to reduce load
-                    sleep(Duration::from_millis(config.flush_interval_seconds * 1000)).await;
-                }
+    info!("Starting to process flow data...");
 
-                if total_processed % 1000 == 0 {
-                    info!(
-                        "Processed: {}, Filtered: {}, Pending: {}",
-                        total_processed,
-                        filtered_out,
-                        batch.len()
-                    );
-                }
-            }
-            Err(e) => {
-                warn!("Failed to parse JSON line: {} - Error: {}", line, e);
-            }
-        }
+    let (flow_tx, flow_rx) =
+        queue::bounded(pipeline::queue_capacity(&config), config.overflow_policy);
+    let (chunk_tx, chunk_rx) = mpsc::channel(pipeline::capacity(&config));
+    let (ack_tx, acks) = watch::channel(0);
+    let reader = tokio::spawn(pipeline::read(
+        input,
+        config.clone(),
+        dead_letter,
+        flow_tx,
+        acks.clone(),
+        shutdown.clone(),
+    ));
+    let transform = tokio::spawn(pipeline::transform(
+        flow_rx,
+        chunk_tx,
+        config.clone(),
+        enrichment,
+        tag_limiter,
+        shutdown.clone(),
+    ));
+    // Each stage stops once the one before it is done, so this returns after the final batch
+    pipeline::write(writer, chunk_rx, ack_tx, config.clone(), shutdown).await;
+    transform.await?;
+    let (mut input, file_starts, read_result) = reader.await?;
+    // The reader stopped listening for acknowledgements before the final batch was written
+    input.acknowledge(*acks.borrow())?;
+
+    let total_processed = METRICS.flows_processed.get();
+    let filtered_out = METRICS.flows_filtered.get();
+    info!(
+        "Processing completed. Total: {}, Filtered: {}, Stale: {}, Future timestamps: {}, Protocol filtered: {}, Unparseable addresses: {}, Deduped: {}, Below threshold: {}, Queue dropped: {}, Parse errors: {}, Schema violations: {}, Conversion errors: {}",
+        total_processed,
+        filtered_out,
+        METRICS.flows_stale.get(),
+        METRICS.future_timestamp.get(),
+        METRICS.flows_proto_filtered.get(),
+        METRICS.unparseable_address.get(),
+        METRICS.flows_deduped.get(),
+        METRICS.flows_below_threshold.get(),
+        METRICS.queue_dropped.get(),
+        METRICS.parse_errors.get(),
+        METRICS.schema_violations.get(),
+        METRICS.conversion_errors.get()
+    );
+    if config.dry_run {
+        info!(
+            "Dry run: would have written {} points in {} batches",
+            METRICS.points_written.get(),
+            METRICS.batches_written.get()
+        );
     }
 
-    if !batch.is_empty() {
-        if let Err(e) = write_batch_with_retry(
-            &client,
-            &config.influxdb_bucket,
-            batch,
-            config.retry_attempts,
-            config.retry_delay_ms,
-        ).await {
-            error!("Failed to write final batch to InfluxDB: {}", e);
-        }
+    let file_ends = file_starts
+        .iter()
+        .skip(1)
+        .map(|(_, processed, filtered)| (*processed, *filtered))
+        .chain([(total_processed, filtered_out)]);
+    for ((file, start_processed, start_filtered), (end_processed, end_filtered)) in
+        file_starts.iter().zip(file_ends)
+    {
+        info!(
+            "  {}: Total: {}, Filtered: {}",
+            file,
+            end_processed - start_processed,
+            end_filtered - start_filtered
+        );
     }
 
-    info!(
-        "Processing completed. Total: {}, Filtered: {}",
-        total_processed, filtered_out
-    );
-
-    Ok(())
+    Ok(read_result?)
 }
@@ -0,0 +1,295 @@
+//! Pipeline counters, optionally exposed in the Prometheus text format on `METRICS_ADDR`.
+
+use axum::{Router, http::header, response::IntoResponse, routing::get};
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+use crate::error::{FlowError, Result};
+
+pub static METRICS: Metrics = Metrics::new();
+
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Self {
+        Counter(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    const fn new() -> Self {
+        Gauge(AtomicU64::new(0))
+    }
+
+    pub fn set(&self, value: u64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Metrics {
+    /// Flows parsed successfully, whether or not they were then filtered out
+    pub flows_processed: Counter,
+    /// Flows dropped by `FILTER_DIRECTION`, `PORT_FILTER`, `AS_FILTER` or `DROP_EMPTY_FLOWS`
+    pub flows_filtered: Counter,
+    /// Flows dropped for having been received more than `MAX_FLOW_AGE_SECONDS` ago
+    pub flows_stale: Counter,
+    /// Flows received further ahead of our clock than `MAX_FUTURE_SKEW_SECONDS`, whether
+    /// dropped or clamped
+    pub future_timestamp: Counter,
+    /// Flows dropped for a protocol missing from `PROTO_ALLOWLIST`
+    pub flows_proto_filtered: Counter,
+    /// Flows with a `src_addr` or `dst_addr` that isn't an IP address, whether kept or
+    /// dropped by `UNPARSEABLE_ADDRESS_POLICY`
+    pub unparseable_address: Counter,
+    /// Flows skipped as repeats of one already seen
+    pub flows_deduped: Counter,
+    /// Flows missing from samplers' sequence numbers, lost before reaching the collector
+    pub dropped_flows: Counter,
+    /// Flows dropped for carrying fewer than `MIN_BYTES` bytes
+    pub flows_below_threshold: Counter,
+    /// Records discarded by `OVERFLOW_POLICY` while the queue after the reader was full
+    pub queue_dropped: Counter,
+    pub parse_errors: Counter,
+    /// Records longer than `MAX_LINE_BYTES`, also counted as parse errors
+    pub oversized_records: Counter,
+    /// UTF-8 byte order marks skipped at the start of input lines or JSON arrays
+    pub byte_order_marks: Counter,
+    /// Well-formed JSON records rejected by `STRICT_SCHEMA`
+    pub schema_violations: Counter,
+    /// Flows that could not be converted to a point and were skipped
+    pub conversion_errors: Counter,
+    /// In a dry run, batches that would have been written; likewise `points_written`
+    pub batches_written: Counter,
+    /// Batches that still failed after every retry
+    pub batch_write_failures: Counter,
+    /// Failed batches written to `SPOOL_DIR`
+    pub batches_spooled: Counter,
+    /// Spooled batches deleted to stay within `SPOOL_MAX_BYTES`
+    pub spool_evictions: Counter,
+    pub batches_replayed: Counter,
+    pub points_written: Counter,
+    /// Points waiting in the batch that is currently being filled
+    pub batch_pending: Gauge,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Metrics {
+            flows_processed: Counter::new(),
+            flows_filtered: Counter::new(),
+            flows_stale: Counter::new(),
+            future_timestamp: Counter::new(),
+            flows_proto_filtered: Counter::new(),
+            unparseable_address: Counter::new(),
+            flows_deduped: Counter::new(),
+            dropped_flows: Counter::new(),
+            flows_below_threshold: Counter::new(),
+            queue_dropped: Counter::new(),
+            parse_errors: Counter::new(),
+            oversized_records: Counter::new(),
+            byte_order_marks: Counter::new(),
+            schema_violations: Counter::new(),
+            conversion_errors: Counter::new(),
+            batches_written: Counter::new(),
+            batch_write_failures: Counter::new(),
+            batches_spooled: Counter::new(),
+            spool_evictions: Counter::new(),
+            batches_replayed: Counter::new(),
+            points_written: Counter::new(),
+            batch_pending: Gauge::new(),
+        }
+    }
+
+    fn render(&self) -> String {
+        let series = [
+            (
+                "flows_processed_total",
+                "counter",
+                "Flows parsed from the input",
+                self.flows_processed.get(),
+            ),
+            (
+                "flows_filtered_total",
+                "counter",
+                "Flows dropped by the address, port or AS filters or as empty",
+                self.flows_filtered.get(),
+            ),
+            (
+                "flows_stale_total",
+                "counter",
+                "Flows dropped as older than MAX_FLOW_AGE_SECONDS",
+                self.flows_stale.get(),
+            ),
+            (
+                "future_timestamp_total",
+                "counter",
+                "Flows timestamped beyond MAX_FUTURE_SKEW_SECONDS ahead",
+                self.future_timestamp.get(),
+            ),
+            (
+                "flows_proto_filtered_total",
+                "counter",
+                "Flows dropped for a protocol missing from PROTO_ALLOWLIST",
+                self.flows_proto_filtered.get(),
+            ),
+            (
+                "unparseable_address_total",
+                "counter",
+                "Flows with a source or destination address that is not an IP address",
+                self.unparseable_address.get(),
+            ),
+            (
+                "flows_deduped_total",
+                "counter",
+                "Flows dropped as duplicates",
+                self.flows_deduped.get(),
+            ),
+            (
+                "dropped_flows_total",
+                "counter",
+                "Flows missing from sampler sequence numbers",
+                self.dropped_flows.get(),
+            ),
+            (
+                "flows_below_threshold_total",
+                "counter",
+                "Flows dropped for being smaller than MIN_BYTES",
+                self.flows_below_threshold.get(),
+            ),
+            (
+                "queue_dropped_total",
+                "counter",
+                "Records dropped by OVERFLOW_POLICY while the read queue was full",
+                self.queue_dropped.get(),
+            ),
+            (
+                "parse_errors_total",
+                "counter",
+                "Records that could not be decoded",
+                self.parse_errors.get(),
+            ),
+            (
+                "oversized_records_total",
+                "counter",
+                "Records longer than MAX_LINE_BYTES",
+                self.oversized_records.get(),
+            ),
+            (
+                "byte_order_marks_total",
+                "counter",
+                "UTF-8 byte order marks skipped in the input",
+                self.byte_order_marks.get(),
+            ),
+            (
+                "schema_violations_total",
+                "counter",
+                "JSON records that did not match the flow schema",
+                self.schema_violations.get(),
+            ),
+            (
+                "conversion_errors_total",
+                "counter",
+                "Flows that could not be converted to a point",
+                self.conversion_errors.get(),
+            ),
+            (
+                "batches_written_total",
+                "counter",
+                "Batches written to InfluxDB",
+                self.batches_written.get(),
+            ),
+            (
+                "batch_write_failures_total",
+                "counter",
+                "Batches that failed after all retries",
+                self.batch_write_failures.get(),
+            ),
+            (
+                "batches_spooled_total",
+                "counter",
+                "Failed batches written to the spool directory",
+                self.batches_spooled.get(),
+            ),
+            (
+                "spool_evictions_total",
+                "counter",
+                "Spooled batches evicted to stay within SPOOL_MAX_BYTES",
+                self.spool_evictions.get(),
+            ),
+            (
+                "batches_replayed_total",
+                "counter",
+                "Spooled batches replayed to InfluxDB",
+                self.batches_replayed.get(),
+            ),
+            (
+                "points_written_total",
+                "counter",
+                "Points written to InfluxDB",
+                self.points_written.get(),
+            ),
+            (
+                "batch_pending",
+                "gauge",
+                "Points in the batch currently being filled",
+                self.batch_pending.get(),
+            ),
+        ];
+
+        let mut out = String::new();
+        for (name, kind, help, value) in series {
+            let _ = writeln!(out, "# HELP goflow2influxdb_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE goflow2influxdb_{} {}", name, kind);
+            let _ = writeln!(out, "goflow2influxdb_{} {}", name, value);
+        }
+        out
+    }
+}
+
+async fn metrics_handler() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        METRICS.render(),
+    )
+}
+
+/// Binds `addr` and serves `/metrics` on a background task.
+pub async fn serve(addr: &str) -> Result<()> {
+    let listener = TcpListener::bind(addr).await.map_err(|e| {
+        FlowError::Connectivity(format!("Failed to bind metrics server {}: {}", addr, e))
+    })?;
+    info!(
+        "Serving metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+
+    let app = Router::new().route("/metrics", get(metrics_handler));
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("Metrics server failed: {}", e);
+        }
+    });
+    Ok(())
+}
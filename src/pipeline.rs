@@ -0,0 +1,840 @@
+//! The processing pipeline: a reader task decodes and filters records, a transform task builds
+//! points from the flows and a writer stage batches and flushes them. The stages are connected
+//! by bounded channels, so a stalled stage holds back the ones before it instead of growing
+//! a backlog.
+//!
+//! Records are numbered as they are read. Every message carries the number of the last record
+//! it accounts for, so writes can be acknowledged back to the input in order.
+
+use futures::{FutureExt, StreamExt, stream::FuturesOrdered};
+use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
+use std::{
+    collections::HashSet,
+    num::NonZeroUsize,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::{mpsc, watch},
+    task::spawn_blocking,
+    time::{Interval, MissedTickBehavior, interval, sleep},
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    AddressClass, Config, FlowData, FutureTimestampPolicy, UnparseableAddressPolicy,
+    aggregate::Aggregator,
+    as_allowed,
+    cardinality::CardinalityLimiter,
+    classify_ip,
+    csv::CsvHeader,
+    dead_letter::DeadLetter,
+    decode_record,
+    dedup::Deduplicator,
+    enrich::Enrichment,
+    error::{FlowError, Result},
+    flow_to_datapoint,
+    input::{Input, InputFormat},
+    measurement_name,
+    metrics::METRICS,
+    point::Point,
+    proto_allowed, queue,
+    rollup::Rollup,
+    sequence::SequenceTracker,
+    writer::Writer,
+};
+
+/// A record passed from the reader to the transform stage. `flow` is `None` for records that
+/// produce no point (blank lines, parse errors, duplicate and filtered flows); they are still
+/// passed on so they get acknowledged.
+pub struct Decoded {
+    seq: u64,
+    flow: Option<FlowData>,
+    /// The input has been idle for `INPUT_IDLE_TIMEOUT_SECONDS`: no record, but what is held
+    /// downstream is written now rather than on the flush timer
+    flush: bool,
+}
+
+/// Points passed from the transform stage to the writer. Every record up to `through` is
+/// accounted for by this chunk or an earlier one.
+pub struct Chunk {
+    points: Vec<Point>,
+    through: u64,
+    /// Write the batch now, passed on from an idle input
+    flush: bool,
+}
+
+impl Chunk {
+    fn new(points: Vec<Point>, through: u64) -> Self {
+        Chunk {
+            points,
+            through,
+            flush: false,
+        }
+    }
+}
+
+/// (file, processed, filtered) totals when each input file was started
+pub type FileStarts = Vec<(String, u64, u64)>;
+
+/// Channel capacity between stages: one batch of slack each.
+pub fn capacity(config: &Config) -> usize {
+    config.batch_size
+}
+
+/// Capacity of the queue after the reader, `QUEUE_CAPACITY` or else `capacity`.
+pub fn queue_capacity(config: &Config) -> usize {
+    config.queue_capacity.unwrap_or_else(|| capacity(config))
+}
+
+fn flush_interval(config: &Config) -> Interval {
+    let mut timer = interval(Duration::from_secs(config.flush_interval_seconds));
+    timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    timer.reset();
+    timer
+}
+
+/// Records decoded together on the blocking pool when `PARSE_WORKERS` is above 1, so handing
+/// them over costs little next to decoding them
+const PARSE_GROUP: usize = 64;
+
+/// A record as read, decoded unless it is a blank JSON or CSV line
+struct Parsed {
+    seq: u64,
+    /// The input file the record came from
+    file: Option<String>,
+    record: Vec<u8>,
+    flow: Option<Result<FlowData>>,
+}
+
+/// A record with its number, file and CSV header, as read
+type ReadRecord = (u64, Option<String>, Option<CsvHeader>, Vec<u8>);
+
+impl Parsed {
+    fn new((seq, file, header, record): ReadRecord, config: &Config) -> Self {
+        let text = config.input_format != InputFormat::Protobuf;
+        let flow = if text && record.len() > config.max_line_bytes {
+            // Lines and array elements were cut short just past the limit while read
+            METRICS.oversized_records.inc();
+            Some(Err(FlowError::Parse(format!(
+                "Record longer than MAX_LINE_BYTES ({} bytes)",
+                config.max_line_bytes
+            ))))
+        } else if text && record.trim_ascii().is_empty() {
+            None
+        } else {
+            Some(decode_record(&record, header.as_ref(), config))
+        };
+        Parsed {
+            seq,
+            file,
+            record,
+            flow,
+        }
+    }
+}
+
+/// Reads records until the input is exhausted or shutdown is requested, then hands the input
+/// back so the last writes can still be acknowledged.
+///
+/// With `PARSE_WORKERS` above 1, records are decoded in groups on the blocking pool, at most
+/// that many groups at a time, then accepted in the order they were read.
+///
+/// Acknowledgements from the writer arrive on `acks` as the number of the last record written.
+pub async fn read(
+    mut input: Input,
+    config: Arc<Config>,
+    dead_letter: Option<DeadLetter>,
+    flows: queue::Sender<Decoded>,
+    mut acks: watch::Receiver<u64>,
+    mut shutdown: watch::Receiver<bool>,
+) -> (Input, FileStarts, Result<()>) {
+    let mut acceptor = Acceptor {
+        dedup: NonZeroUsize::new(config.dedup_window).map(Deduplicator::new),
+        sequences: config
+            .sequence_gap_tracking
+            .then(|| SequenceTracker::new(config.sequence_gap_tolerance)),
+        dead_letter,
+        warnings: WarningLimiter::new(config.parse_error_log_limit),
+        unsampled: HashSet::new(),
+        defaulted: HashSet::new(),
+        file_starts: FileStarts::new(),
+    };
+    let mut parsing = FuturesOrdered::new();
+    // How reading ended, when that was found out while filling a group
+    let mut ended = None;
+    let mut seq = 0;
+    // The dead letter file is flushed on this timer, like the batches downstream
+    let mut flush_timer = flush_interval(&config);
+    // Restarted by every record read; a quiet spell flushes once
+    let idle_timeout = config.input_idle_timeout_seconds.map(Duration::from_secs);
+    let idle = sleep(idle_timeout.unwrap_or(Duration::MAX));
+    tokio::pin!(idle);
+    let mut idle_flushed = false;
+
+    let mut result = 'read: loop {
+        if let Some(result) = ended.take() {
+            break result;
+        }
+
+        let group: Vec<Parsed> = tokio::select! {
+            _ = shutdown.changed() => {
+                info!("Shutdown signal received, flushing pending batch...");
+                break Ok(());
+            }
+            Ok(()) = acks.changed() => {
+                let through = *acks.borrow_and_update();
+                if let Err(e) = input.acknowledge(through) {
+                    error!("Failed to acknowledge written records: {}", e);
+                }
+                continue;
+            }
+            Some(group) = parsing.next() => match group {
+                Ok(group) => group,
+                Err(e) => break Err(FlowError::Parse(format!("Parse worker failed: {}", e))),
+            },
+            record = input.next_record(), if parsing.len() < config.parse_workers => {
+                let record = match record {
+                    Ok(Some(record)) => record,
+                    Ok(None) => break Ok(()),
+                    Err(e) => break Err(e),
+                };
+                if let Some(timeout) = idle_timeout {
+                    idle.as_mut().reset(tokio::time::Instant::now() + timeout);
+                    idle_flushed = false;
+                }
+                seq += 1;
+                let file = input.current_file().map(str::to_string);
+                let read = (seq, file, input.csv_header(), record);
+                if config.parse_workers == 1 {
+                    vec![Parsed::new(read, &config)]
+                } else {
+                    // Whatever else is ready right away joins the group, without waiting
+                    let mut records = vec![read];
+                    while records.len() < PARSE_GROUP {
+                        match input.next_record().now_or_never() {
+                            Some(Ok(Some(record))) => {
+                                seq += 1;
+                                let file = input.current_file().map(str::to_string);
+                                records.push((seq, file, input.csv_header(), record));
+                            }
+                            Some(Ok(None)) => {
+                                ended = Some(Ok(()));
+                                break;
+                            }
+                            Some(Err(e)) => {
+                                ended = Some(Err(e));
+                                break;
+                            }
+                            None => break,
+                        }
+                    }
+                    let config = config.clone();
+                    parsing.push_back(spawn_blocking(move || {
+                        records
+                            .into_iter()
+                            .map(|read| Parsed::new(read, &config))
+                            .collect()
+                    }));
+                    continue;
+                }
+            }
+            _ = flush_timer.tick() => {
+                flush_dead_letter(&mut acceptor.dead_letter).await;
+                acceptor.warnings.roll();
+                continue;
+            }
+            // Records still being decoded aren't idleness
+            _ = &mut idle, if idle_timeout.is_some() && !idle_flushed && parsing.is_empty() => {
+                let seconds = config.input_idle_timeout_seconds.unwrap_or_default();
+                if config.input_idle_exit {
+                    warn!("No input for {} seconds, stopping", seconds);
+                    break Ok(());
+                }
+                info!("No input for {} seconds, flushing pending batch", seconds);
+                idle_flushed = true;
+                let flush = Decoded {
+                    seq,
+                    flow: None,
+                    flush: true,
+                };
+                if flows.send(flush).await.is_err() {
+                    break Ok(());
+                }
+                continue;
+            }
+        };
+
+        for parsed in group {
+            if !acceptor.pass_on(parsed, &config, &flows).await {
+                break 'read Ok(());
+            }
+        }
+    };
+
+    // Records already read are still passed on, so they get written
+    'drain: while let Some(group) = parsing.next().await {
+        match group {
+            Ok(group) => {
+                for parsed in group {
+                    if !acceptor.pass_on(parsed, &config, &flows).await {
+                        break 'drain;
+                    }
+                }
+            }
+            Err(e) => {
+                result = result.and(Err(FlowError::Parse(format!("Parse worker failed: {}", e))))
+            }
+        }
+    }
+
+    flush_dead_letter(&mut acceptor.dead_letter).await;
+    acceptor.warnings.report();
+    (input, acceptor.file_starts, result)
+}
+
+/// The reader's state for deciding which flows are passed on, kept across records.
+struct Acceptor {
+    dedup: Option<Deduplicator>,
+    sequences: Option<SequenceTracker>,
+    dead_letter: Option<DeadLetter>,
+    warnings: WarningLimiter,
+    /// Samplers already reported as sending a sampling rate of 0
+    unsampled: HashSet<String>,
+    /// Fields already reported as missing from records and defaulted
+    defaulted: HashSet<&'static str>,
+    file_starts: FileStarts,
+}
+
+impl Acceptor {
+    /// Passes `parsed` on to the transform stage, returning false once that has stopped.
+    async fn pass_on(
+        &mut self,
+        parsed: Parsed,
+        config: &Config,
+        flows: &queue::Sender<Decoded>,
+    ) -> bool {
+        if let Some(file) = &parsed.file
+            && self
+                .file_starts
+                .last()
+                .is_none_or(|(name, ..)| name != file)
+        {
+            self.file_starts.push((
+                file.clone(),
+                METRICS.flows_processed.get(),
+                METRICS.flows_filtered.get(),
+            ));
+        }
+
+        let flow = match parsed.flow {
+            Some(flow) => self.accept(&parsed.record, flow, config).await,
+            None => None,
+        };
+        flows
+            .send(Decoded {
+                seq: parsed.seq,
+                flow,
+                flush: false,
+            })
+            .await
+            .is_ok()
+    }
+
+    /// Returns the flow decoded from `record` unless it is a duplicate, filtered out, empty
+    /// or below `MIN_BYTES`. A sampling rate of 0 is normalized to 1.
+    async fn accept(
+        &mut self,
+        record: &[u8],
+        decoded: Result<FlowData>,
+        config: &Config,
+    ) -> Option<FlowData> {
+        let mut flow = match decoded {
+            Ok(flow) => flow,
+            Err(e) => {
+                let schema_violation = matches!(e, FlowError::Schema(_));
+                if schema_violation {
+                    METRICS.schema_violations.inc();
+                } else {
+                    METRICS.parse_errors.inc();
+                }
+                match config.input_format {
+                    _ if !self.warnings.allow() => {}
+                    InputFormat::Json | InputFormat::JsonArray if schema_violation => warn!(
+                        "JSON line does not match the flow schema: {} - Error: {}",
+                        String::from_utf8_lossy(record),
+                        e
+                    ),
+                    InputFormat::Json | InputFormat::JsonArray => warn!(
+                        "Failed to parse JSON line: {} - Error: {}",
+                        String::from_utf8_lossy(record),
+                        e
+                    ),
+                    InputFormat::Csv => warn!(
+                        "Failed to parse CSV row: {} - Error: {}",
+                        String::from_utf8_lossy(record),
+                        e
+                    ),
+                    InputFormat::Protobuf => warn!(
+                        "Failed to decode protobuf record ({} bytes) - Error: {}",
+                        record.len(),
+                        e
+                    ),
+                }
+
+                if let Some(dead_letter) = &mut self.dead_letter
+                    && let Err(e) = dead_letter.write(record, &e).await
+                {
+                    error!("Failed to write to dead letter file: {}", e);
+                }
+                return None;
+            }
+        };
+
+        METRICS.flows_processed.inc();
+        for field in std::mem::take(&mut flow.defaulted) {
+            if field == "time_received_ns" {
+                flow.time_received_ns = now_ns() / config.timestamp_precision.nanos_per_unit();
+            }
+            if self.defaulted.insert(field) {
+                warn!(
+                    "Flows from {} are missing {}, defaulting it to {}",
+                    flow.sampler_address,
+                    field,
+                    if field == "time_received_ns" {
+                        "now"
+                    } else {
+                        "0"
+                    }
+                );
+            }
+        }
+        // Exporters report 0 for unsampled traffic, which would zero out scaled counters
+        if flow.sampling_rate == 0 {
+            flow.sampling_rate = 1;
+            if !self.unsampled.contains(&flow.sampler_address) {
+                info!(
+                    "Sampler {} reports a sampling rate of 0, treating it as 1 (unsampled)",
+                    flow.sampler_address
+                );
+                self.unsampled.insert(flow.sampler_address.clone());
+            }
+        }
+
+        if METRICS.flows_processed.get().is_multiple_of(1000) {
+            info!(
+                "Processed: {}, Filtered: {}, Deduped: {}, Below threshold: {}, Parse errors: {}, Schema violations: {}, Pending: {}",
+                METRICS.flows_processed.get(),
+                METRICS.flows_filtered.get(),
+                METRICS.flows_deduped.get(),
+                METRICS.flows_below_threshold.get(),
+                METRICS.parse_errors.get(),
+                METRICS.schema_violations.get(),
+                METRICS.batch_pending.get()
+            );
+        }
+
+        if let Some(dedup) = &mut self.dedup
+            && dedup.is_duplicate(&flow)
+        {
+            METRICS.flows_deduped.inc();
+            return None;
+        }
+        // Before filtering, as filtered flows still arrived
+        if let Some(sequences) = &mut self.sequences {
+            flow.dropped_flows = sequences.observe(&flow);
+        }
+
+        // In TIMESTAMP_PRECISION units, like the other times of the flow
+        let received_ns = config.timestamp_precision.to_nanos(flow.time_received_ns) as u64;
+        // A receive time of 0 is unset rather than ancient, so such flows are kept
+        if let Some(max_age) = config.max_flow_age_seconds
+            && received_ns != 0
+            && now_ns().saturating_sub(received_ns) > max_age.saturating_mul(1_000_000_000)
+        {
+            METRICS.flows_stale.inc();
+            return None;
+        }
+
+        if let Some(max_skew) = config.max_future_skew_seconds {
+            let now = now_ns();
+            let ahead = received_ns.saturating_sub(now);
+            if ahead > max_skew.saturating_mul(1_000_000_000) {
+                METRICS.future_timestamp.inc();
+                warn!(
+                    "Flow from {} was received {}s in the future",
+                    flow.sampler_address,
+                    ahead / 1_000_000_000
+                );
+                match config.future_timestamp_policy {
+                    FutureTimestampPolicy::Drop => return None,
+                    FutureTimestampPolicy::Clamp => {
+                        flow.time_received_ns = now / config.timestamp_precision.nanos_per_unit()
+                    }
+                }
+            }
+        }
+
+        if !proto_allowed(&flow.proto, &config.proto_allowlist) {
+            METRICS.flows_proto_filtered.inc();
+            return None;
+        }
+
+        let src = classify_ip(&flow.src_addr, &config.filter_cidrs);
+        let dst = classify_ip(&flow.dst_addr, &config.filter_cidrs);
+
+        if src == AddressClass::Unparseable || dst == AddressClass::Unparseable {
+            METRICS.unparseable_address.inc();
+            match config.unparseable_address_policy {
+                UnparseableAddressPolicy::Keep => {}
+                UnparseableAddressPolicy::Drop => return None,
+                UnparseableAddressPolicy::DeadLetter => {
+                    if let Some(dead_letter) = &mut self.dead_letter {
+                        let e = FlowError::Conversion(format!(
+                            "Unparseable address in flow from {} to {}",
+                            flow.src_addr, flow.dst_addr
+                        ));
+                        if let Err(e) = dead_letter.write(record, &e).await {
+                            error!("Failed to write to dead letter file: {}", e);
+                        }
+                    }
+                    return None;
+                }
+            }
+        } else {
+            let keep = config
+                .filter_direction
+                .keeps(src == AddressClass::Private, dst == AddressClass::Private);
+
+            // FILTER_INVERT flips the decision, keeping what the direction would drop
+            if keep == config.filter_invert {
+                METRICS.flows_filtered.inc();
+                return None;
+            }
+        }
+
+        if !config.port_filter_side.keeps(&config.port_filter, &flow) {
+            METRICS.flows_filtered.inc();
+            return None;
+        }
+
+        if !as_allowed(&flow, config) {
+            METRICS.flows_filtered.inc();
+            return None;
+        }
+
+        if config.drop_empty_flows && config.empty_flow_condition.matches(&flow) {
+            METRICS.flows_filtered.inc();
+            return None;
+        }
+
+        if flow.bytes < config.min_bytes {
+            METRICS.flows_below_threshold.inc();
+            return None;
+        }
+
+        Some(flow)
+    }
+}
+
+/// Caps the parse error warnings logged per minute (`PARSE_ERROR_LOG_LIMIT`, 0 for no cap),
+/// so a mostly malformed input can't drown out everything else. Suppressed warnings are
+/// summarized once the minute is over; the error counters stay exact.
+struct WarningLimiter {
+    limit: u32,
+    window_start: Instant,
+    logged: u32,
+    suppressed: u64,
+}
+
+impl WarningLimiter {
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    fn new(limit: u32) -> Self {
+        WarningLimiter {
+            limit,
+            window_start: Instant::now(),
+            logged: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// Whether the next warning may be logged.
+    fn allow(&mut self) -> bool {
+        if self.limit == 0 {
+            return true;
+        }
+        self.roll();
+        if self.logged < self.limit {
+            self.logged += 1;
+            return true;
+        }
+        self.suppressed += 1;
+        false
+    }
+
+    /// Starts a new window once the current one is over.
+    fn roll(&mut self) {
+        if self.window_start.elapsed() >= Self::WINDOW {
+            self.report();
+            self.window_start = Instant::now();
+            self.logged = 0;
+        }
+    }
+
+    fn report(&mut self) {
+        if self.suppressed > 0 {
+            warn!(
+                "{} more parse errors were not logged (PARSE_ERROR_LOG_LIMIT is {} per minute)",
+                self.suppressed, self.limit
+            );
+            self.suppressed = 0;
+        }
+    }
+}
+
+async fn flush_dead_letter(dead_letter: &mut Option<DeadLetter>) {
+    if let Some(dead_letter) = dead_letter
+        && let Err(e) = dead_letter.flush().await
+    {
+        error!("Failed to flush dead letter file: {}", e);
+    }
+}
+
+/// Builds points from flows, rate limiting and aggregating them on the way, until the reader
+/// is done.
+pub async fn transform(
+    mut flows: queue::Receiver<Decoded>,
+    chunks: mpsc::Sender<Chunk>,
+    config: Arc<Config>,
+    enrichment: Enrichment,
+    tag_limiter: CardinalityLimiter,
+    shutdown: watch::Receiver<bool>,
+) {
+    let mut aggregator = config.aggregate_5tuple.then(Aggregator::default);
+    let mut rollup = config.rollup_enabled.then(Rollup::default);
+    // Records held in an open rollup window aren't acknowledged until it is written
+    let through = |rollup: &Option<Rollup>, seq: u64| {
+        rollup
+            .as_ref()
+            .and_then(Rollup::held_from)
+            .map_or(seq, |held_from| held_from.saturating_sub(1))
+    };
+    let rate_limiter: Option<DefaultDirectRateLimiter> = config
+        .max_points_per_sec
+        .map(|rate| RateLimiter::direct(Quota::per_second(rate)));
+    // A flow that can't be converted is skipped; it's still acknowledged with the rest
+    let to_point = |flow: &FlowData, now: u64| match flow_to_datapoint(
+        flow,
+        &measurement_name(flow, &config),
+        &config,
+        &enrichment,
+        &tag_limiter,
+        now,
+    ) {
+        Ok(point) => Some(point),
+        Err(e) => {
+            METRICS.conversion_errors.inc();
+            warn!("Skipping flow from {}: {}", flow.sampler_address, e);
+            None
+        }
+    };
+    // The aggregation window closes on this timer, so merged flows aren't held back when
+    // traffic is low; so do rollup windows once traffic stops
+    let mut window_timer = flush_interval(&config);
+    let mut seq = 0;
+    let mut group = Vec::new();
+
+    'transform: loop {
+        // Flows are taken as they were queued, up to a batch at a time, and share one reading
+        // of the clock
+        let now = tokio::select! {
+            received = flows.recv_many(&mut group, config.batch_size) => {
+                if received == 0 {
+                    break;
+                }
+                now_ns()
+            }
+            _ = window_timer.tick(), if aggregator.is_some() || rollup.is_some() => {
+                if let Some(aggregator) = &mut aggregator {
+                    let now = now_ns();
+                    let points = aggregator
+                        .drain()
+                        .filter_map(|flow| to_point(&flow, now))
+                        .collect();
+                    let _ = chunks.send(Chunk::new(points, seq)).await;
+                }
+                if let Some(window) = &mut rollup {
+                    let points = window.tick(now_ns(), &config);
+                    if !points.is_empty() {
+                        let through = through(&rollup, seq);
+                        let _ = chunks.send(Chunk::new(points, through)).await;
+                    }
+                }
+                continue;
+            }
+        };
+        for decoded in group.drain(..) {
+            seq = decoded.seq;
+
+            if decoded.flush {
+                // Merged flows are written too; rollup windows still close on their own time
+                let points = match &mut aggregator {
+                    Some(aggregator) => aggregator
+                        .drain()
+                        .filter_map(|flow| to_point(&flow, now))
+                        .collect(),
+                    None => Vec::new(),
+                };
+                let flush = Chunk {
+                    points,
+                    through: through(&rollup, seq),
+                    flush: true,
+                };
+                if chunks.send(flush).await.is_err() {
+                    break 'transform;
+                }
+                continue;
+            }
+
+            let Some(flow) = decoded.flow else {
+                // Records waiting in the aggregator must be written before later ones are
+                // acknowledged
+                if aggregator
+                    .as_ref()
+                    .is_none_or(|aggregator| aggregator.len() == 0)
+                    && rollup.as_ref().is_none_or(|rollup| rollup.len() == 0)
+                {
+                    let _ = chunks.send(Chunk::new(Vec::new(), seq)).await;
+                }
+                continue;
+            };
+
+            // Holds the reader back, so the effective rate doesn't depend on batch boundaries.
+            // Flows still in the pipeline at shutdown are written without waiting.
+            if let Some(limiter) = &rate_limiter
+                && !*shutdown.borrow()
+            {
+                limiter.until_ready().await;
+            }
+
+            if let Some(window) = &mut rollup {
+                let points = window.add(&flow, seq, &config);
+                if points.is_empty() {
+                    continue;
+                }
+                let through = through(&rollup, seq);
+                if chunks.send(Chunk::new(points, through)).await.is_err() {
+                    break 'transform;
+                }
+                continue;
+            }
+
+            let points = match &mut aggregator {
+                Some(aggregator) => {
+                    aggregator.add(flow);
+                    // Flush early once the window holds a full batch, bounding memory
+                    if aggregator.len() < config.batch_size {
+                        continue;
+                    }
+                    aggregator
+                        .drain()
+                        .filter_map(|flow| to_point(&flow, now))
+                        .collect()
+                }
+                None => to_point(&flow, now).into_iter().collect(),
+            };
+            if chunks.send(Chunk::new(points, seq)).await.is_err() {
+                break 'transform;
+            }
+        }
+    }
+
+    if let Some(aggregator) = &mut aggregator {
+        let now = now_ns();
+        let points = aggregator
+            .drain()
+            .filter_map(|flow| to_point(&flow, now))
+            .collect();
+        let _ = chunks.send(Chunk::new(points, seq)).await;
+    }
+    if let Some(rollup) = &mut rollup {
+        let points = rollup.drain(&config);
+        let _ = chunks.send(Chunk::new(points, seq)).await;
+    }
+}
+
+/// The current time in nanoseconds since the UNIX epoch. Points take it once per group of
+/// flows taken from the queue, or per closed window, rather than per flow.
+fn now_ns() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+/// Batches points and writes them, full batches as soon as they fill up and partial ones
+/// every `FLUSH_INTERVAL_SECONDS`, until the transform stage is done. Successful writes are
+/// reported on `acks`.
+pub async fn write(
+    writer: Writer,
+    mut chunks: mpsc::Receiver<Chunk>,
+    acks: watch::Sender<u64>,
+    config: Arc<Config>,
+    shutdown: watch::Receiver<bool>,
+) {
+    let mut batch = Vec::new();
+    let mut through = 0;
+    // Partial batches are flushed on this timer so they don't sit in memory when traffic is low
+    let mut flush_timer = flush_interval(&config);
+
+    loop {
+        tokio::select! {
+            chunk = chunks.recv() => {
+                let Some(chunk) = chunk else {
+                    break;
+                };
+                batch.extend(chunk.points);
+                through = chunk.through;
+                METRICS.batch_pending.set(batch.len() as u64);
+
+                if chunk.flush {
+                    write_batch(&writer, &mut batch, through, &acks, &shutdown).await;
+                    flush_timer.reset();
+                } else if batch.len() >= config.batch_size {
+                    write_batch(&writer, &mut batch, through, &acks, &shutdown).await;
+                    flush_timer.reset();
+
+                    // Optional pacing between full batches to reduce load on InfluxDB
+                    if config.inter_batch_delay_ms > 0 {
+                        sleep(Duration::from_millis(config.inter_batch_delay_ms)).await;
+                    }
+                }
+            }
+            _ = flush_timer.tick() => {
+                write_batch(&writer, &mut batch, through, &acks, &shutdown).await;
+            }
+        }
+    }
+
+    write_batch(&writer, &mut batch, through, &acks, &shutdown).await;
+    // Queued batches, including the final one, are written before the summary
+    writer.finish().await;
+}
+
+async fn write_batch(
+    writer: &Writer,
+    batch: &mut Vec<Point>,
+    through: u64,
+    acks: &watch::Sender<u64>,
+    shutdown: &watch::Receiver<bool>,
+) {
+    let batch = std::mem::take(batch);
+    METRICS.batch_pending.set(0);
+    match writer.write(batch, shutdown.clone()).await {
+        Ok(()) => {
+            acks.send_replace(through);
+        }
+        Err(e) => error!("Failed to write batch to InfluxDB: {}", e),
+    }
+}
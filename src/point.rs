@@ -0,0 +1,215 @@
+//! InfluxDB points, built the way `influxdb2`'s `DataPoint` is but with unsigned integer
+//! fields, which that type can't hold.
+
+use influxdb2::models::WriteDataPoint;
+use std::{borrow::Cow, collections::BTreeMap, io};
+
+use crate::error::{FlowError, Result};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldValue {
+    Bool(bool),
+    F64(f64),
+    I64(i64),
+    /// Written with the `u` suffix, so counters keep their full `u64` range
+    U64(u64),
+    String(String),
+}
+
+impl From<bool> for FieldValue {
+    fn from(value: bool) -> Self {
+        FieldValue::Bool(value)
+    }
+}
+
+impl From<f64> for FieldValue {
+    fn from(value: f64) -> Self {
+        FieldValue::F64(value)
+    }
+}
+
+impl From<i64> for FieldValue {
+    fn from(value: i64) -> Self {
+        FieldValue::I64(value)
+    }
+}
+
+impl From<u64> for FieldValue {
+    fn from(value: u64) -> Self {
+        FieldValue::U64(value)
+    }
+}
+
+impl From<&str> for FieldValue {
+    fn from(value: &str) -> Self {
+        FieldValue::String(value.to_string())
+    }
+}
+
+impl From<String> for FieldValue {
+    fn from(value: String) -> Self {
+        FieldValue::String(value)
+    }
+}
+
+#[derive(Debug)]
+pub struct PointBuilder {
+    measurement: String,
+    // Sorted, like `DataPoint`, which keeps the line protocol output stable
+    tags: BTreeMap<String, String>,
+    fields: BTreeMap<String, FieldValue>,
+    timestamp: Option<i64>,
+    bucket: Option<String>,
+}
+
+impl PointBuilder {
+    /// Sets a tag, replacing any existing tag of the same name.
+    pub fn tag(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.insert(name.into(), value.into());
+        self
+    }
+
+    /// Sets a field, replacing any existing field of the same name.
+    pub fn field(mut self, name: impl Into<String>, value: impl Into<FieldValue>) -> Self {
+        self.fields.insert(name.into(), value.into());
+        self
+    }
+
+    /// Removes the fields `keep` returns false for.
+    pub fn retain_fields(mut self, mut keep: impl FnMut(&str) -> bool) -> Self {
+        self.fields.retain(|name, _| keep(name));
+        self
+    }
+
+    /// Sets the timestamp, in nanoseconds since the UNIX epoch.
+    pub fn timestamp(mut self, value: i64) -> Self {
+        self.timestamp = Some(value);
+        self
+    }
+
+    /// Sends the point to `bucket` instead of the default one. Not part of the line protocol.
+    pub fn bucket(mut self, bucket: impl Into<String>) -> Self {
+        self.bucket = Some(bucket.into());
+        self
+    }
+
+    /// Fails if no field was set, as a point without fields can't be written.
+    pub fn build(self) -> Result<Point> {
+        if self.fields.is_empty() {
+            return Err(FlowError::Conversion(format!(
+                "Point in {} has no fields",
+                self.measurement
+            )));
+        }
+
+        Ok(Point {
+            measurement: self.measurement,
+            tags: self.tags,
+            fields: self.fields,
+            timestamp: self.timestamp,
+            bucket: self.bucket,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Point {
+    measurement: String,
+    tags: BTreeMap<String, String>,
+    fields: BTreeMap<String, FieldValue>,
+    timestamp: Option<i64>,
+    bucket: Option<String>,
+}
+
+impl Point {
+    pub fn builder(measurement: impl Into<String>) -> PointBuilder {
+        PointBuilder {
+            measurement: measurement.into(),
+            tags: BTreeMap::new(),
+            fields: BTreeMap::new(),
+            timestamp: None,
+            bucket: None,
+        }
+    }
+
+    /// The bucket the point is routed to, or `None` for the default bucket.
+    pub fn bucket(&self) -> Option<&str> {
+        self.bucket.as_deref()
+    }
+}
+
+/// Splits `batch` by target bucket, in order of first appearance. A batch for a single
+/// bucket, the usual case, is passed through without copying.
+pub fn by_bucket(batch: &[Point]) -> Vec<(Option<&str>, Cow<'_, [Point]>)> {
+    let first = batch.first().and_then(Point::bucket);
+    if batch.iter().all(|point| point.bucket() == first) {
+        return vec![(first, Cow::Borrowed(batch))];
+    }
+
+    let mut groups: Vec<(Option<&str>, Vec<Point>)> = Vec::new();
+    for point in batch {
+        match groups
+            .iter_mut()
+            .find(|(bucket, _)| *bucket == point.bucket())
+        {
+            Some((_, points)) => points.push(point.clone()),
+            None => groups.push((point.bucket(), vec![point.clone()])),
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(bucket, points)| (bucket, Cow::Owned(points)))
+        .collect()
+}
+
+impl WriteDataPoint for Point {
+    fn write_data_point_to<W>(&self, mut w: W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        write_escaped(&mut w, &self.measurement, &[',', ' '])?;
+        // Line protocol has no empty tag values, such as a non-IP flow's src_addr
+        for (key, value) in self.tags.iter().filter(|(_, value)| !value.is_empty()) {
+            w.write_all(b",")?;
+            write_escaped(&mut w, key, KEY_DELIMITERS)?;
+            w.write_all(b"=")?;
+            write_escaped(&mut w, value, KEY_DELIMITERS)?;
+        }
+
+        for (i, (key, value)) in self.fields.iter().enumerate() {
+            w.write_all(if i == 0 { b" " } else { b"," })?;
+            write_escaped(&mut w, key, KEY_DELIMITERS)?;
+            w.write_all(b"=")?;
+            match value {
+                FieldValue::Bool(v) => w.write_all(if *v { b"t" } else { b"f" })?,
+                FieldValue::F64(v) => write!(w, "{}", v)?,
+                FieldValue::I64(v) => write!(w, "{}i", v)?,
+                FieldValue::U64(v) => write!(w, "{}u", v)?,
+                FieldValue::String(v) => {
+                    w.write_all(b"\"")?;
+                    write_escaped(&mut w, v, &['"', '\\'])?;
+                    w.write_all(b"\"")?;
+                }
+            }
+        }
+
+        if let Some(timestamp) = self.timestamp {
+            write!(w, " {}", timestamp)?;
+        }
+        w.write_all(b"\n")
+    }
+}
+
+/// Characters escaped in tag keys, tag values and field keys
+const KEY_DELIMITERS: &[char] = &[',', '=', ' '];
+
+fn write_escaped<W: io::Write>(w: &mut W, value: &str, delimiters: &[char]) -> io::Result<()> {
+    let mut last = 0;
+    for (i, delimiter) in value.match_indices(delimiters) {
+        w.write_all(&value.as_bytes()[last..i])?;
+        w.write_all(b"\\")?;
+        w.write_all(delimiter.as_bytes())?;
+        last = i + delimiter.len();
+    }
+    w.write_all(&value.as_bytes()[last..])
+}
@@ -0,0 +1,184 @@
+//! The bounded queue between the reader and the rest of the pipeline, with a choice of what
+//! happens to records while it is full (`OVERFLOW_POLICY`).
+
+use std::{
+    collections::VecDeque,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::Notify;
+
+use crate::{
+    error::{FlowError, Result},
+    metrics::METRICS,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverflowPolicy {
+    /// Wait for room, holding the reader back
+    Block,
+    /// Discard the record being queued
+    DropNewest,
+    /// Discard the longest-queued record to make room; the reader never waits, so a UDP
+    /// socket keeps being drained and the newest flows win
+    DropOldest,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "block" => Ok(OverflowPolicy::Block),
+            "drop_newest" => Ok(OverflowPolicy::DropNewest),
+            "drop_oldest" => Ok(OverflowPolicy::DropOldest),
+            other => Err(FlowError::Config(format!(
+                "Invalid OVERFLOW_POLICY '{}', expected one of: block, drop_newest, drop_oldest",
+                other
+            ))),
+        }
+    }
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    sender_closed: bool,
+    receiver_closed: bool,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    /// Wakes the receiver when an item is queued or the sender is dropped
+    pushed: Notify,
+    /// Wakes a blocked sender when an item is taken or the receiver is dropped
+    taken: Notify,
+}
+
+/// A single-producer, single-consumer queue holding at most `capacity` items. Records
+/// dropped by the policy are counted in `queue_dropped`.
+pub fn bounded<T>(capacity: usize, policy: OverflowPolicy) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            items: VecDeque::with_capacity(capacity),
+            sender_closed: false,
+            receiver_closed: false,
+        }),
+        capacity: capacity.max(1),
+        policy,
+        pushed: Notify::new(),
+        taken: Notify::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Queues `item`, applying the overflow policy while the queue is full. Fails, handing
+    /// the item back, once the receiver is gone.
+    pub async fn send(&self, item: T) -> std::result::Result<(), T> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if state.receiver_closed {
+                    return Err(item);
+                }
+                if state.items.len() < self.shared.capacity {
+                    state.items.push_back(item);
+                    drop(state);
+                    self.shared.pushed.notify_one();
+                    return Ok(());
+                }
+
+                match self.shared.policy {
+                    OverflowPolicy::Block => {}
+                    OverflowPolicy::DropNewest => {
+                        METRICS.queue_dropped.inc();
+                        return Ok(());
+                    }
+                    OverflowPolicy::DropOldest => {
+                        state.items.pop_front();
+                        state.items.push_back(item);
+                        METRICS.queue_dropped.inc();
+                        return Ok(());
+                    }
+                }
+            }
+            // A wakeup between releasing the lock and waiting is kept as a permit
+            self.shared.taken.notified().await;
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().sender_closed = true;
+        self.shared.pushed.notify_one();
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Takes the oldest item, waiting for one, or `None` once the queue is empty and the
+    /// sender is gone.
+    ///
+    /// Cancel safe, so it can be used as a `tokio::select!` branch.
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if let Some(item) = state.items.pop_front() {
+                    drop(state);
+                    self.shared.taken.notify_one();
+                    return Some(item);
+                }
+                if state.sender_closed {
+                    return None;
+                }
+            }
+            self.shared.pushed.notified().await;
+        }
+    }
+
+    /// Takes every queued item into `buf`, at most `limit`, waiting for at least one.
+    /// Returns how many were taken, 0 once the queue is empty and the sender is gone.
+    ///
+    /// Cancel safe, like `recv`.
+    pub async fn recv_many(&mut self, buf: &mut Vec<T>, limit: usize) -> usize {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if !state.items.is_empty() {
+                    let taken = state.items.len().min(limit.max(1));
+                    buf.extend(state.items.drain(..taken));
+                    drop(state);
+                    self.shared.taken.notify_one();
+                    return taken;
+                }
+                if state.sender_closed {
+                    return 0;
+                }
+            }
+            self.shared.pushed.notified().await;
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().receiver_closed = true;
+        self.shared.taken.notify_one();
+    }
+}
@@ -0,0 +1,155 @@
+//! Minute rollups (`ROLLUP_ENABLED`): instead of a point per flow, one point per sampler and
+//! protocol per minute, for long-term dashboards that don't need individual flows.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{Config, FlowData, error::Result, point::Point, proto_name};
+
+const WINDOW_NS: i64 = 60_000_000_000;
+
+/// (sampler_address, proto), with protocol numbers named where possible so that exporters
+/// sending `6` and `TCP` roll up together
+type RollupKey = (String, String);
+
+struct Window {
+    totals: HashMap<RollupKey, Totals>,
+    /// Number of the first record counted in the window, see `Rollup::held_from`
+    first_seq: u64,
+}
+
+#[derive(Default)]
+struct Totals {
+    bytes: u64,
+    packets: u64,
+    flows: u64,
+}
+
+/// Sums flows into windows aligned to minute boundaries, by the flow's point timestamp.
+///
+/// A window closes once a flow from a later minute arrives, so replayed files roll up the
+/// same way live traffic does, or when no flow has arrived since the last `tick` and the
+/// minute is over by the wall clock. Flows arriving after their window closed are counted in
+/// the oldest open one rather than writing the closed window's point again, which would
+/// replace it.
+#[derive(Default)]
+pub struct Rollup {
+    windows: BTreeMap<i64, Window>,
+    /// Start of the first window that is still open
+    open_from: i64,
+    /// Latest flow timestamp seen, and what it was at the last tick
+    watermark: i64,
+    ticked_watermark: i64,
+}
+
+impl Rollup {
+    /// Adds `flow`, read as record number `seq`, returning the points of the windows it
+    /// closed.
+    pub fn add(&mut self, flow: &FlowData, seq: u64, config: &Config) -> Vec<Point> {
+        let timestamp = config
+            .timestamp_precision
+            .to_nanos(config.timestamp_source.value(flow));
+        let start = (timestamp - timestamp.rem_euclid(WINDOW_NS)).max(self.open_from);
+
+        let (bytes, packets) = if config.apply_sampling_scale {
+            let rate = flow.sampling_rate as u64;
+            (
+                flow.bytes.saturating_mul(rate),
+                flow.packets.saturating_mul(rate),
+            )
+        } else {
+            (flow.bytes, flow.packets)
+        };
+        let totals = self
+            .windows
+            .entry(start)
+            .or_insert_with(|| Window {
+                totals: HashMap::new(),
+                first_seq: seq,
+            })
+            .totals
+            .entry((
+                flow.sampler_address.clone(),
+                proto_name(&flow.proto).to_string(),
+            ))
+            .or_default();
+        totals.bytes = totals.bytes.saturating_add(bytes);
+        totals.packets = totals.packets.saturating_add(packets);
+        totals.flows += 1;
+
+        self.watermark = self.watermark.max(timestamp);
+        self.close(start, config)
+    }
+
+    /// Closes the windows that are over by the wall clock (`now_ns`), if no flow arrived
+    /// since the previous tick.
+    pub fn tick(&mut self, now_ns: u64, config: &Config) -> Vec<Point> {
+        let idle = self.watermark == self.ticked_watermark;
+        self.ticked_watermark = self.watermark;
+        if !idle {
+            return Vec::new();
+        }
+        let now = i64::try_from(now_ns).unwrap_or(i64::MAX);
+        self.close(now - now.rem_euclid(WINDOW_NS), config)
+    }
+
+    /// Totals held in open windows, one per sampler and protocol per window.
+    pub fn len(&self) -> usize {
+        self.windows
+            .values()
+            .map(|window| window.totals.len())
+            .sum()
+    }
+
+    /// The number of the first record still held in an open window, which must not be
+    /// acknowledged until that window is written.
+    pub fn held_from(&self) -> Option<u64> {
+        self.windows.values().map(|window| window.first_seq).min()
+    }
+
+    /// Closes every window, for shutdown.
+    pub fn drain(&mut self, config: &Config) -> Vec<Point> {
+        self.close(i64::MAX, config)
+    }
+
+    /// Closes the windows starting before `before`, returning their points.
+    fn close(&mut self, before: i64, config: &Config) -> Vec<Point> {
+        let open = self.windows.split_off(&before);
+        let closed = std::mem::replace(&mut self.windows, open);
+        let mut points = Vec::new();
+        for (start, window) in closed {
+            self.open_from = self.open_from.max(start.saturating_add(WINDOW_NS));
+            for ((sampler_address, proto), totals) in window.totals {
+                // Always has fields, so building can't fail
+                if let Ok(point) = rollup_point(start, &sampler_address, &proto, &totals, config) {
+                    points.push(point);
+                }
+            }
+        }
+        points
+    }
+}
+
+fn rollup_point(
+    start: i64,
+    sampler_address: &str,
+    proto: &str,
+    totals: &Totals,
+    config: &Config,
+) -> Result<Point> {
+    let mut builder = Point::builder(format!("{}_1m", config.measurement_name));
+    for (key, value) in &config.static_tags {
+        builder = builder.tag(key, value);
+    }
+    if let Some(hostname) = &config.collector_hostname {
+        builder = builder.tag("collector", hostname);
+    }
+    // The protocol is named already, so unlike flow points there is no separate proto_name
+    builder
+        .tag("sampler_address", sampler_address)
+        .tag("proto", proto)
+        .field("bytes", totals.bytes)
+        .field("packets", totals.packets)
+        .field("flows", totals.flows)
+        .timestamp(start)
+        .build()
+}
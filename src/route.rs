@@ -0,0 +1,160 @@
+//! Routing of flows to buckets other than `INFLUXDB_BUCKET` by rule (`BUCKET_ROUTES`), so
+//! e.g. security flows can be kept with their own retention.
+
+use std::str::FromStr;
+
+use crate::{
+    FlowData,
+    error::{FlowError, Result},
+    proto_name,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RouteField {
+    FlowType,
+    Proto,
+    SrcAddr,
+    DstAddr,
+    SamplerAddress,
+    SrcPort,
+    DstPort,
+}
+
+impl RouteField {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "flow_type" => Some(RouteField::FlowType),
+            "proto" => Some(RouteField::Proto),
+            "src_addr" => Some(RouteField::SrcAddr),
+            "dst_addr" => Some(RouteField::DstAddr),
+            "sampler_address" => Some(RouteField::SamplerAddress),
+            "src_port" => Some(RouteField::SrcPort),
+            "dst_port" => Some(RouteField::DstPort),
+            _ => None,
+        }
+    }
+}
+
+/// One `field==value` or `field!=value` comparison.
+#[derive(Debug, Clone, PartialEq)]
+struct Condition {
+    field: RouteField,
+    value: String,
+    /// The value parsed, for the port fields
+    port: Option<u16>,
+    negated: bool,
+}
+
+impl Condition {
+    fn matches(&self, flow: &FlowData) -> bool {
+        let equal = match self.field {
+            RouteField::FlowType => flow.flow_type.eq_ignore_ascii_case(&self.value),
+            // By name, so `proto==TCP` also matches flows carrying the number 6
+            RouteField::Proto => proto_name(&flow.proto).eq_ignore_ascii_case(&self.value),
+            RouteField::SrcAddr => flow.src_addr == self.value,
+            RouteField::DstAddr => flow.dst_addr == self.value,
+            RouteField::SamplerAddress => flow.sampler_address == self.value,
+            RouteField::SrcPort => self.port == Some(flow.src_port),
+            RouteField::DstPort => self.port == Some(flow.dst_port),
+        };
+        equal != self.negated
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Route {
+    conditions: Vec<Condition>,
+    bucket: String,
+}
+
+/// Rules of the form `proto==TCP&&dst_port==443=>web`, separated by commas. A flow goes to
+/// the bucket of the first rule whose conditions all match, or to `INFLUXDB_BUCKET` when
+/// none does. With InfluxDB 1.x, the bucket names a database.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BucketRouter {
+    routes: Vec<Route>,
+}
+
+impl BucketRouter {
+    /// The bucket `flow` is routed to, or `None` for the default bucket.
+    pub fn route(&self, flow: &FlowData) -> Option<&str> {
+        self.routes
+            .iter()
+            .find(|route| route.conditions.iter().all(|c| c.matches(flow)))
+            .map(|route| route.bucket.as_str())
+    }
+}
+
+impl FromStr for BucketRouter {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |entry: &str, reason: &str| {
+            FlowError::Config(format!(
+                "Invalid route '{}' in BUCKET_ROUTES: {}",
+                entry, reason
+            ))
+        };
+
+        let mut routes = Vec::new();
+        for entry in s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let Some((conditions, bucket)) = entry.rsplit_once("=>") else {
+                return Err(invalid(entry, "expected conditions=>bucket"));
+            };
+            let bucket = bucket.trim();
+            if bucket.is_empty() {
+                return Err(invalid(entry, "no bucket given"));
+            }
+
+            let conditions = conditions
+                .split("&&")
+                .map(|condition| {
+                    let condition = condition.trim();
+                    let (field, value, negated) = match condition.split_once("!=") {
+                        Some((field, value)) => (field, value, true),
+                        None => match condition.split_once("==") {
+                            Some((field, value)) => (field, value, false),
+                            None => return Err(invalid(entry, "expected field==value")),
+                        },
+                    };
+                    let field = RouteField::parse(field.trim()).ok_or_else(|| {
+                        invalid(
+                            entry,
+                            "fields are flow_type, proto, src_addr, dst_addr, sampler_address, \
+                             src_port and dst_port",
+                        )
+                    })?;
+                    let value = match field {
+                        // Numbers with a name are stored by name, like PROTO_ALLOWLIST entries
+                        RouteField::Proto => proto_name(value.trim()),
+                        _ => value.trim(),
+                    };
+                    let port = match field {
+                        RouteField::SrcPort | RouteField::DstPort => Some(
+                            value
+                                .parse()
+                                .map_err(|_| invalid(entry, "ports must be numbers"))?,
+                        ),
+                        _ => None,
+                    };
+                    Ok(Condition {
+                        field,
+                        value: value.to_string(),
+                        port,
+                        negated,
+                    })
+                })
+                .collect::<Result<_>>()?;
+
+            routes.push(Route {
+                conditions,
+                bucket: bucket.to_string(),
+            });
+        }
+        Ok(BucketRouter { routes })
+    }
+}
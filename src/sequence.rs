@@ -0,0 +1,76 @@
+//! Detection of flows lost between exporter and collector, from gaps in the sequence
+//! numbers each sampler puts on its exports (`SEQUENCE_GAP_TRACKING`).
+
+use std::collections::HashMap;
+use tracing::warn;
+
+use crate::{FlowData, metrics::METRICS};
+
+/// (sampler_address, flow_type, observation_domain_id): sequence numbers are counted per
+/// exporter and, for NetFlow v9/IPFIX, per observation domain
+type SamplerKey = (String, String, u32);
+
+/// Remembers the highest sequence number seen per sampler.
+///
+/// NetFlow v9 and sFlow number export packets, whose flows share one sequence number;
+/// NetFlow v5 and IPFIX number the flows themselves, so a packet's number is the previous
+/// one plus the flows the previous packet carried. Numbers compare modulo 2^32, so `u32`
+/// wraparound is an ordinary step forward. A number up to `tolerance` behind the highest is
+/// a late arrival and ignored (the gap it left was already counted); anything further behind
+/// means the exporter restarted, and tracking starts over from it.
+pub struct SequenceTracker {
+    /// Highest sequence number, and the flows seen carrying it
+    last: HashMap<SamplerKey, (u32, u32)>,
+    tolerance: u32,
+}
+
+impl SequenceTracker {
+    pub fn new(tolerance: u32) -> Self {
+        SequenceTracker {
+            last: HashMap::new(),
+            tolerance,
+        }
+    }
+
+    /// Records `flow`'s sequence number, returning how many flows (packets, for NetFlow v9
+    /// and sFlow) went missing just before it.
+    pub fn observe(&mut self, flow: &FlowData) -> u64 {
+        let key = (
+            flow.sampler_address.clone(),
+            flow.flow_type.clone(),
+            flow.observation_domain_id.unwrap_or(0),
+        );
+        let seq = flow.sequence_num;
+        let Some((last, flows)) = self.last.get_mut(&key) else {
+            self.last.insert(key, (seq, 1));
+            return 0;
+        };
+
+        let ahead = seq.wrapping_sub(*last);
+        if ahead == 0 {
+            *flows += 1;
+            return 0;
+        }
+        if ahead > u32::MAX / 2 {
+            if last.wrapping_sub(seq) > self.tolerance {
+                (*last, *flows) = (seq, 1);
+            }
+            return 0;
+        }
+
+        let expected = match flow.flow_type.as_str() {
+            "NETFLOW_V5" | "IPFIX" => *flows,
+            _ => 1,
+        };
+        (*last, *flows) = (seq, 1);
+        let missing = u64::from(ahead.saturating_sub(expected));
+        if missing > 0 {
+            METRICS.dropped_flows.add(missing);
+            warn!(
+                "Sequence gap from {}: {} missing before sequence number {}",
+                flow.sampler_address, missing, seq
+            );
+        }
+        missing
+    }
+}
@@ -0,0 +1,181 @@
+//! Destinations that batches of points are written to.
+
+use futures::{future::BoxFuture, stream};
+use influxdb2::{Client, models::WriteDataPoint};
+use std::sync::Arc;
+
+use crate::{
+    Config,
+    error::{FlowError, Result},
+    point::Point,
+};
+
+/// Writes one batch of points, to `bucket` or when that is `None` to `INFLUXDB_BUCKET`
+/// (`INFLUXDB_DATABASE` with InfluxDB 1.x). Retrying is left to the caller.
+pub trait Sink: Send + Sync {
+    fn write<'a>(
+        &'a self,
+        bucket: Option<&'a str>,
+        batch: &'a [Point],
+    ) -> BoxFuture<'a, Result<()>>;
+
+    /// Writes points already rendered as line protocol, such as a spooled batch.
+    fn write_lines<'a>(
+        &'a self,
+        bucket: Option<&'a str>,
+        lines: Vec<u8>,
+    ) -> BoxFuture<'a, Result<()>>;
+
+    /// Checks that the server is reachable, without writing anything.
+    fn ping(&self) -> BoxFuture<'_, Result<()>>;
+}
+
+pub fn from_config(config: &Config) -> Arc<dyn Sink> {
+    if config.influxdb_version == 1 {
+        return Arc::new(InfluxV1::new(config));
+    }
+
+    Arc::new(InfluxV2 {
+        client: Client::new(
+            &config.influxdb_url,
+            &config.influxdb_org,
+            &config.influxdb_token,
+        ),
+        org: config.influxdb_org.clone(),
+        bucket: config.influxdb_bucket.clone(),
+    })
+}
+
+/// InfluxDB 2.x org/bucket writes through the official client.
+pub struct InfluxV2 {
+    client: Client,
+    org: String,
+    bucket: String,
+}
+
+impl Sink for InfluxV2 {
+    fn write<'a>(
+        &'a self,
+        bucket: Option<&'a str>,
+        batch: &'a [Point],
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.client
+                .write(bucket.unwrap_or(&self.bucket), stream::iter(batch.to_vec()))
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn write_lines<'a>(
+        &'a self,
+        bucket: Option<&'a str>,
+        lines: Vec<u8>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.client
+                .write_line_protocol(&self.org, bucket.unwrap_or(&self.bucket), lines)
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn ping(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            if !self.client.ready().await? {
+                return Err(FlowError::Connectivity("InfluxDB is not ready".to_string()));
+            }
+            Ok(())
+        })
+    }
+}
+
+/// InfluxDB 1.x database/retention policy writes to the `/write` line protocol endpoint.
+pub struct InfluxV1 {
+    http: reqwest::Client,
+    write_url: String,
+    ping_url: String,
+    database: String,
+    /// Query parameters besides the database
+    query: Vec<(&'static str, String)>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl InfluxV1 {
+    fn new(config: &Config) -> Self {
+        let mut query = vec![("precision", "ns".to_string())];
+        if let Some(rp) = &config.influxdb_retention_policy {
+            query.push(("rp", rp.clone()));
+        }
+
+        InfluxV1 {
+            http: reqwest::Client::new(),
+            write_url: format!("{}/write", config.influxdb_url.trim_end_matches('/')),
+            ping_url: format!("{}/ping", config.influxdb_url.trim_end_matches('/')),
+            database: config.influxdb_database.clone(),
+            query,
+            username: config.influxdb_username.clone(),
+            password: config.influxdb_password.clone(),
+        }
+    }
+}
+
+impl Sink for InfluxV1 {
+    fn write<'a>(
+        &'a self,
+        bucket: Option<&'a str>,
+        batch: &'a [Point],
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut body = Vec::new();
+            for point in batch {
+                point.write_data_point_to(&mut body)?;
+            }
+            self.write_lines(bucket, body).await
+        })
+    }
+
+    fn write_lines<'a>(
+        &'a self,
+        bucket: Option<&'a str>,
+        lines: Vec<u8>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut request = self
+                .http
+                .post(&self.write_url)
+                .query(&[("db", bucket.unwrap_or(&self.database))])
+                .query(&self.query)
+                .body(lines);
+            if let Some(username) = &self.username {
+                request = request.basic_auth(username, self.password.as_ref());
+            }
+
+            let response = request.send().await?;
+            let status = response.status();
+            if !status.is_success() {
+                let text = response.text().await.unwrap_or_default();
+                return Err(FlowError::Write(format!(
+                    "InfluxDB returned {}: {}",
+                    status,
+                    text.trim()
+                )));
+            }
+            Ok(())
+        })
+    }
+
+    fn ping(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let status = self.http.get(&self.ping_url).send().await?.status();
+            if !status.is_success() {
+                return Err(FlowError::Connectivity(format!(
+                    "InfluxDB ping returned {}",
+                    status
+                )));
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,199 @@
+//! Write-ahead spool (`SPOOL_DIR`) of batches that could not be written, replayed once
+//! InfluxDB is reachable again so flows survive an outage or a restart during one.
+
+use influxdb2::models::WriteDataPoint;
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs,
+    sync::Mutex,
+    time::{Instant, interval_at},
+};
+use tracing::{info, warn};
+
+use crate::{
+    error::{FlowError, Result},
+    metrics::METRICS,
+    point::{Point, by_bucket},
+    sink::Sink,
+};
+
+/// One line protocol file per batch and target bucket. File names start with the time the
+/// batch was spooled, so sorting them by name gives the oldest first, and end with the
+/// hex-encoded bucket for points routed away from the default one.
+pub struct Spool {
+    dir: PathBuf,
+    /// Total size of the spooled files; the oldest are evicted to stay within it
+    max_bytes: u64,
+    /// Makes eviction and the write that follows it one step across write workers
+    lock: Mutex<()>,
+    sequence: AtomicU64,
+}
+
+impl Spool {
+    pub async fn open(dir: &str, max_bytes: u64) -> Result<Self> {
+        fs::create_dir_all(dir).await.map_err(|e| {
+            FlowError::Config(format!("Failed to create spool directory {}: {}", dir, e))
+        })?;
+
+        let spool = Spool {
+            dir: PathBuf::from(dir),
+            max_bytes,
+            lock: Mutex::new(()),
+            sequence: AtomicU64::new(0),
+        };
+        let files = spool.files().await?;
+        if !files.is_empty() {
+            let bytes: u64 = files.iter().map(|(_, size)| size).sum();
+            info!(
+                "Found {} spooled batches ({} bytes) in {}",
+                files.len(),
+                bytes,
+                dir
+            );
+        }
+        Ok(spool)
+    }
+
+    /// Appends `batch` to the spool, evicting the oldest batches if it would not fit.
+    pub async fn store(&self, batch: &[Point]) -> Result<()> {
+        let mut files = Vec::new();
+        for (bucket, points) in by_bucket(batch) {
+            let mut lines = Vec::new();
+            for point in points.iter() {
+                point.write_data_point_to(&mut lines)?;
+            }
+            files.push((bucket, lines));
+        }
+        let len: u64 = files.iter().map(|(_, lines)| lines.len() as u64).sum();
+        if len > self.max_bytes {
+            return Err(FlowError::Write(format!(
+                "Batch of {} bytes is larger than SPOOL_MAX_BYTES ({})",
+                len, self.max_bytes
+            )));
+        }
+
+        let _guard = self.lock.lock().await;
+        let spooled = self.files().await?;
+        let mut total: u64 = spooled.iter().map(|(_, size)| size).sum();
+        for (path, size) in spooled {
+            if total + len <= self.max_bytes {
+                break;
+            }
+            match fs::remove_file(&path).await {
+                Ok(()) => {
+                    warn!("Spool full, evicted {}", path.display());
+                    METRICS.spool_evictions.inc();
+                }
+                // Replayed in the meantime
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+            total -= size;
+        }
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        for (bucket, lines) in files {
+            let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+            let mut name = format!("{:020}-{:06}", nanos, sequence);
+            if let Some(bucket) = bucket {
+                name.push('-');
+                for byte in bucket.bytes() {
+                    name.push_str(&format!("{:02x}", byte));
+                }
+            }
+            // Written under a temporary name so replay never sees a partial file
+            let partial = self.dir.join(format!("{}.tmp", name));
+            fs::write(&partial, &lines).await?;
+            fs::rename(&partial, self.dir.join(format!("{}.lp", name))).await?;
+        }
+
+        METRICS.batches_spooled.inc();
+        info!("Spooled batch of {} points", batch.len());
+        Ok(())
+    }
+
+    /// Writes spooled batches oldest first, deleting each once written. Stops at the first
+    /// failure, leaving that batch and the ones after it for the next attempt.
+    async fn replay(&self, sink: &dyn Sink) -> Result<()> {
+        for (path, _) in self.files().await? {
+            let lines = match fs::read(&path).await {
+                Ok(lines) => lines,
+                // Evicted in the meantime
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            sink.write_lines(bucket(&path).as_deref(), lines).await?;
+            if let Err(e) = fs::remove_file(&path).await
+                && e.kind() != std::io::ErrorKind::NotFound
+            {
+                return Err(e.into());
+            }
+            METRICS.batches_replayed.inc();
+            info!("Replayed spooled batch {}", path.display());
+        }
+        Ok(())
+    }
+
+    /// Replays the spool if it holds anything and InfluxDB answers a ping, logging failures.
+    pub async fn try_replay(&self, sink: &dyn Sink) {
+        match self.files().await {
+            Ok(files) if files.is_empty() => return,
+            Ok(_) => {}
+            Err(e) => {
+                warn!("Failed to list spool directory: {}", e);
+                return;
+            }
+        }
+        if sink.ping().await.is_err() {
+            return;
+        }
+        if let Err(e) = self.replay(sink).await {
+            warn!("Spool replay stopped: {}", e);
+        }
+    }
+
+    /// Spooled batches and their sizes, oldest first.
+    async fn files(&self) -> Result<Vec<(PathBuf, u64)>> {
+        let mut files = Vec::new();
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "lp") {
+                files.push((path, entry.metadata().await?.len()));
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+}
+
+/// The bucket a spooled file's points were routed to, decoded from its name.
+fn bucket(path: &Path) -> Option<String> {
+    let hex = path.file_stem()?.to_str()?.splitn(3, '-').nth(2)?;
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// Replays the spool every `every`, see `Spool::try_replay`.
+pub fn spawn_replay(spool: Arc<Spool>, sink: Arc<dyn Sink>, every: Duration) {
+    tokio::spawn(async move {
+        let mut ticks = interval_at(Instant::now() + every, every);
+        loop {
+            ticks.tick().await;
+            spool.try_replay(&*sink).await;
+        }
+    });
+}
@@ -0,0 +1,344 @@
+//! Batch writing: a pool of `WRITE_WORKERS` tasks fed through a bounded queue, so a slow
+//! InfluxDB applies backpressure to the reader instead of stalling it per write.
+
+use influxdb2::models::WriteDataPoint;
+use rand::{RngExt, rngs::StdRng};
+use std::{io::Write, str::FromStr, sync::Arc, time::Duration};
+use tokio::{
+    sync::{Mutex, mpsc, watch},
+    task::JoinHandle,
+    time::sleep,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    Config,
+    circuit::CircuitBreaker,
+    error::{FlowError, Result},
+    health::HEALTH,
+    metrics::METRICS,
+    point::{Point, by_bucket},
+    sink::Sink,
+    spool::Spool,
+};
+
+/// What a dry run does with the batches it would have written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DryRunOutput {
+    /// Only count them
+    Count,
+    /// Print them to stdout as line protocol
+    LineProtocol,
+}
+
+impl FromStr for DryRunOutput {
+    type Err = FlowError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "count" => Ok(DryRunOutput::Count),
+            "line_protocol" => Ok(DryRunOutput::LineProtocol),
+            other => Err(FlowError::Config(format!(
+                "Invalid DRY_RUN_OUTPUT '{}', expected one of: count, line_protocol",
+                other
+            ))),
+        }
+    }
+}
+
+pub struct Writer {
+    sink: Arc<dyn Sink>,
+    breaker: Arc<CircuitBreaker>,
+    config: Arc<Config>,
+    /// Whether the input tracks delivery, see `Input::tracks_delivery`
+    tracks_delivery: bool,
+    queue: mpsc::Sender<Vec<Point>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Writer {
+    pub fn new(
+        sink: Arc<dyn Sink>,
+        breaker: Arc<CircuitBreaker>,
+        config: Arc<Config>,
+        tracks_delivery: bool,
+        spool: Option<Arc<Spool>>,
+    ) -> Self {
+        let worker_count = config.write_workers;
+        // One queued batch per worker; beyond that the reader waits
+        let (queue, rx) = mpsc::channel(worker_count);
+        let rx = Arc::new(Mutex::new(rx));
+
+        let workers = (0..worker_count)
+            .map(|_| {
+                let (sink, breaker, config, spool, rx) = (
+                    sink.clone(),
+                    breaker.clone(),
+                    config.clone(),
+                    spool.clone(),
+                    rx.clone(),
+                );
+                tokio::spawn(async move {
+                    loop {
+                        // The lock is only held while waiting, not while writing
+                        let Some(batch) = rx.lock().await.recv().await else {
+                            return;
+                        };
+                        let spool = spool.as_deref();
+                        if let Err(e) =
+                            write_untracked(&*sink, &breaker, &config, spool, batch).await
+                        {
+                            error!("Failed to write batch to InfluxDB: {}", e);
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        Writer {
+            sink,
+            breaker,
+            config,
+            tracks_delivery,
+            queue,
+            workers,
+        }
+    }
+
+    /// Hands `batch` to the writers, waiting while they are all busy.
+    ///
+    /// Batches from inputs that track delivery (Kafka) are instead written here, in order, so
+    /// `Ok` means the batch can be acknowledged. They must not lose records, so a failed write
+    /// is retried until it succeeds, which pauses consumption, unless shutdown has been
+    /// requested.
+    pub async fn write(
+        &self,
+        batch: Vec<Point>,
+        mut shutdown: watch::Receiver<bool>,
+    ) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        if !self.tracks_delivery {
+            return self
+                .queue
+                .send(batch)
+                .await
+                .map_err(|_| FlowError::Write("Write workers have stopped".to_string()));
+        }
+
+        let (sink, breaker, config) = (&*self.sink, &*self.breaker, &*self.config);
+        loop {
+            let result = if breaker.allow() {
+                flush_batch(sink, breaker, config, &batch).await
+            } else {
+                Err(FlowError::Write("Circuit breaker open".to_string()))
+            };
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    // Unacknowledged records are redelivered after a restart, so it's safe to stop
+                    if *shutdown.borrow() {
+                        return Err(e);
+                    }
+                    error!("{}. Pausing input until InfluxDB recovers", e);
+                    tokio::select! {
+                        _ = shutdown.changed() => return Err(e),
+                        _ = sleep(Duration::from_millis(config.retry_delay_ms)) => {}
+                    }
+                }
+            }
+        }
+    }
+
+    /// Waits for every queued batch to be written and the workers to exit.
+    pub async fn finish(self) {
+        drop(self.queue);
+        for worker in self.workers {
+            let _ = worker.await;
+        }
+    }
+}
+
+/// Writes a batch from an input that doesn't track delivery, dropping or buffering it per
+/// `CIRCUIT_BREAKER_POLICY` while the circuit breaker is open. With a spool, batches that
+/// fail every retry or meet an open circuit are spooled instead.
+async fn write_untracked(
+    sink: &dyn Sink,
+    breaker: &CircuitBreaker,
+    config: &Config,
+    spool: Option<&Spool>,
+    batch: Vec<Point>,
+) -> Result<()> {
+    if !breaker.allow() {
+        return match spool {
+            Some(spool) => spool.store(&batch).await,
+            None => breaker.reject(batch).await,
+        };
+    }
+
+    match (flush_batch(sink, breaker, config, &batch).await, spool) {
+        (Err(e), Some(spool)) => {
+            warn!("{}. Spooling batch of {} points", e, batch.len());
+            spool.store(&batch).await
+        }
+        (result, _) => result,
+    }
+}
+
+async fn flush_batch(
+    sink: &dyn Sink,
+    breaker: &CircuitBreaker,
+    config: &Config,
+    batch: &[Point],
+) -> Result<()> {
+    if config.dry_run {
+        if config.dry_run_output == DryRunOutput::LineProtocol {
+            let mut lines = Vec::new();
+            for point in batch {
+                point.write_data_point_to(&mut lines)?;
+            }
+            // One write per batch keeps batches from concurrent workers from interleaving
+            std::io::stdout().lock().write_all(&lines)?;
+        }
+        info!("Dry run: would write batch of {} points", batch.len());
+        METRICS.batches_written.inc();
+        METRICS.points_written.add(batch.len() as u64);
+        return Ok(());
+    }
+
+    let result = write_batch_with_retry(sink, batch, &mut RetryPolicy::from_config(config)).await;
+    breaker.record(result.is_ok());
+    result
+}
+
+/// How often, and how patiently, a failed batch write is retried.
+pub struct RetryPolicy {
+    attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    /// Source of full jitter, so collectors recovering from the same outage spread out
+    jitter: Option<StdRng>,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        RetryPolicy {
+            attempts: config.retry_attempts,
+            base_delay: Duration::from_millis(config.retry_delay_ms),
+            max_delay: Duration::from_millis(config.retry_max_delay_ms),
+            jitter: config.retry_jitter.then(rand::make_rng),
+        }
+    }
+
+    /// Backoff after failed attempt `attempt` (1-based): the base delay, doubled for each
+    /// further attempt, capped at `max_delay`.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    /// The backoff, or with jitter a uniformly random delay between 0 and the backoff.
+    fn delay(&mut self, attempt: u32) -> Duration {
+        let backoff = self.backoff(attempt);
+        match &mut self.jitter {
+            Some(rng) => {
+                let max_ms = u64::try_from(backoff.as_millis()).unwrap_or(u64::MAX);
+                Duration::from_millis(rng.random_range(0..=max_ms))
+            }
+            None => backoff,
+        }
+    }
+}
+
+/// Writes `batch` with one write per target bucket, retrying each with `policy`'s backoff
+/// until it succeeds or the attempts run out. A failure leaves the buckets written before it
+/// written, so the whole batch may be written again; points overwrite their duplicates.
+pub async fn write_batch_with_retry(
+    sink: &dyn Sink,
+    batch: &[Point],
+    policy: &mut RetryPolicy,
+) -> Result<()> {
+    for (bucket, points) in by_bucket(batch) {
+        write_with_retry(sink, bucket, &points, policy).await?;
+    }
+    Ok(())
+}
+
+async fn write_with_retry(
+    sink: &dyn Sink,
+    bucket: Option<&str>,
+    batch: &[Point],
+    policy: &mut RetryPolicy,
+) -> Result<()> {
+    let retry_attempts = policy.attempts;
+    for attempt in 1..=retry_attempts {
+        match sink.write(bucket, batch).await {
+            Ok(_) => {
+                info!(
+                    "Successfully wrote batch of {} points to InfluxDB",
+                    batch.len()
+                );
+                METRICS.batches_written.inc();
+                METRICS.points_written.add(batch.len() as u64);
+                HEALTH.record_write(true);
+                return Ok(());
+            }
+            Err(e) => {
+                if attempt == retry_attempts {
+                    METRICS.batch_write_failures.inc();
+                    HEALTH.record_write(false);
+                    return Err(FlowError::Write(format!(
+                        "Failed to write batch after {} attempts: {}",
+                        retry_attempts, e
+                    )));
+                }
+                let delay = policy.delay(attempt);
+                warn!(
+                    "Attempt {}/{} failed: {}. Retrying in {}ms...",
+                    attempt,
+                    retry_attempts,
+                    e,
+                    delay.as_millis()
+                );
+                sleep(delay).await;
+            }
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn retry_backoff_doubles_up_to_the_cap() {
+        let mut policy = RetryPolicy {
+            attempts: 6,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            jitter: None,
+        };
+        let delays: Vec<_> = (1..=5).map(|attempt| policy.delay(attempt)).collect();
+        assert_eq!(delays, [100, 200, 400, 500, 500].map(Duration::from_millis));
+    }
+
+    #[test]
+    fn retry_jitter_stays_within_backoff_and_is_seedable() {
+        let policy = |seed| RetryPolicy {
+            attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(2_000),
+            jitter: Some(StdRng::seed_from_u64(seed)),
+        };
+        let (mut a, mut b) = (policy(42), policy(42));
+
+        for attempt in 1..=10 {
+            let delay = a.delay(attempt);
+            assert!(delay <= a.backoff(attempt));
+            assert_eq!(delay, b.delay(attempt));
+        }
+    }
+}
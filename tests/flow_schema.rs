@@ -0,0 +1,230 @@
+//! Golden files for the goflow2 JSON schema: one sample record per flow type in `fixtures/`,
+//! with every field the exporter sends checked, so a renamed field fails here rather than
+//! silently parsing as missing.
+
+use GoFlow2Influxdb::FlowData;
+
+fn fixture(name: &str) -> String {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+    std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path, e))
+}
+
+fn parse(name: &str) -> FlowData {
+    let record = fixture(name);
+    serde_json::from_str(record.trim_end()).unwrap_or_else(|e| panic!("{}: {}", name, e))
+}
+
+#[test]
+fn parses_sflow() {
+    let flow = parse("sflow.json");
+
+    assert_eq!(flow.flow_type, "SFLOW_5");
+    assert_eq!(flow.time_received_ns, 1755046784453621258);
+    assert_eq!(flow.sequence_num, 5120);
+    assert_eq!(flow.sampling_rate, 1000);
+    assert_eq!(flow.sampler_address, "192.0.2.10");
+    assert_eq!(flow.time_flow_start_ns, 1755046784453621258);
+    assert_eq!(flow.time_flow_end_ns, 1755046784453621258);
+    assert_eq!(flow.bytes, 1514);
+    assert_eq!(flow.packets, 1);
+    assert_eq!(flow.src_addr, "192.168.10.20");
+    assert_eq!(flow.dst_addr, "151.101.1.69");
+    assert_eq!(flow.etype, "IPv4");
+    assert_eq!(flow.proto, "TCP");
+    assert_eq!(flow.src_port, 51234);
+    assert_eq!(flow.dst_port, 443);
+    assert_eq!(flow.in_if, 3);
+    assert_eq!(flow.out_if, 7);
+    assert_eq!(flow.src_mac.as_deref(), Some("00:11:22:33:44:55"));
+    assert_eq!(flow.dst_mac.as_deref(), Some("66:77:88:99:aa:bb"));
+    assert_eq!(flow.src_vlan, Some(10));
+    assert_eq!(flow.dst_vlan, Some(20));
+    assert_eq!(flow.vlan_id, Some(10));
+    assert_eq!(flow.ip_tos, Some(0));
+    assert_eq!(flow.forwarding_status, Some(0));
+    assert_eq!(flow.ip_ttl, Some(64));
+    assert_eq!(flow.ip_flags, Some(2));
+    assert_eq!(flow.tcp_flags, Some(16));
+    assert_eq!(flow.icmp_type, Some(0));
+    assert_eq!(flow.icmp_code, Some(0));
+    assert_eq!(flow.ipv6_flow_label, Some(0));
+    assert_eq!(flow.fragment_id, Some(54321));
+    assert_eq!(flow.fragment_offset, Some(0));
+    assert_eq!(flow.src_as, Some(0));
+    assert_eq!(flow.dst_as, Some(54113));
+    assert_eq!(flow.next_hop.as_deref(), Some(""));
+    assert_eq!(flow.observation_domain_id, Some(0));
+    assert_eq!(flow.observation_point_id, Some(0));
+    // Not sent by sFlow agents
+    assert_eq!(flow.next_hop_as, None);
+    assert_eq!(flow.src_net, None);
+    assert_eq!(flow.bgp_next_hop, None);
+    assert_eq!(flow.as_path, None);
+    assert_eq!(flow.mpls_label, None);
+}
+
+#[test]
+fn parses_netflow_v5() {
+    let flow = parse("netflow_v5.json");
+
+    assert_eq!(flow.flow_type, "NETFLOW_V5");
+    assert_eq!(flow.time_received_ns, 1755046790000000000);
+    assert_eq!(flow.sequence_num, 1200);
+    assert_eq!(flow.sampling_rate, 0);
+    assert_eq!(flow.sampler_address, "192.0.2.20");
+    assert_eq!(flow.time_flow_start_ns, 1755046760000000000);
+    assert_eq!(flow.time_flow_end_ns, 1755046789000000000);
+    assert_eq!(flow.bytes, 4096);
+    assert_eq!(flow.packets, 8);
+    assert_eq!(flow.src_addr, "10.1.2.3");
+    assert_eq!(flow.dst_addr, "8.8.8.8");
+    assert_eq!(flow.etype, "IPv4");
+    assert_eq!(flow.proto, "UDP");
+    assert_eq!(flow.src_port, 53211);
+    assert_eq!(flow.dst_port, 53);
+    assert_eq!(flow.in_if, 1);
+    assert_eq!(flow.out_if, 2);
+    assert_eq!(flow.ip_tos, Some(184));
+    assert_eq!(flow.tcp_flags, Some(0));
+    assert_eq!(flow.src_as, Some(64512));
+    assert_eq!(flow.dst_as, Some(15169));
+    assert_eq!(flow.next_hop.as_deref(), Some("192.0.2.254"));
+    assert_eq!(flow.src_net.as_deref(), Some("10.1.0.0/16"));
+    assert_eq!(flow.dst_net.as_deref(), Some("8.8.8.0/24"));
+    // The v5 record has no L2, TTL or MPLS fields
+    assert_eq!(flow.src_mac, None);
+    assert_eq!(flow.src_vlan, None);
+    assert_eq!(flow.ip_ttl, None);
+    assert_eq!(flow.forwarding_status, None);
+    assert_eq!(flow.mpls_label, None);
+    assert_eq!(flow.observation_domain_id, None);
+}
+
+#[test]
+fn parses_netflow_v9() {
+    let flow = parse("netflow_v9.json");
+
+    assert_eq!(flow.flow_type, "NETFLOW_V9");
+    assert_eq!(flow.time_received_ns, 1755046795000000000);
+    assert_eq!(flow.sequence_num, 880);
+    assert_eq!(flow.sampling_rate, 100);
+    assert_eq!(flow.sampler_address, "2001:db8::1");
+    assert_eq!(flow.time_flow_start_ns, 1755046780000000000);
+    assert_eq!(flow.time_flow_end_ns, 1755046794000000000);
+    assert_eq!(flow.bytes, 65000);
+    assert_eq!(flow.packets, 50);
+    assert_eq!(flow.src_addr, "2001:db8:100::10");
+    assert_eq!(flow.dst_addr, "2606:4700::1111");
+    assert_eq!(flow.etype, "IPv6");
+    assert_eq!(flow.proto, "TCP");
+    assert_eq!(flow.src_port, 40000);
+    assert_eq!(flow.dst_port, 443);
+    assert_eq!(flow.in_if, 10);
+    assert_eq!(flow.out_if, 20);
+    assert_eq!(flow.ip_tos, Some(0));
+    assert_eq!(flow.forwarding_status, Some(64));
+    assert_eq!(flow.ip_ttl, Some(57));
+    assert_eq!(flow.tcp_flags, Some(27));
+    assert_eq!(flow.ipv6_flow_label, Some(712345));
+    assert_eq!(flow.src_as, Some(64513));
+    assert_eq!(flow.dst_as, Some(13335));
+    assert_eq!(flow.next_hop.as_deref(), Some("2001:db8::fe"));
+    assert_eq!(flow.next_hop_as, Some(3356));
+    assert_eq!(flow.src_net.as_deref(), Some("2001:db8:100::/48"));
+    assert_eq!(flow.dst_net.as_deref(), Some("2606:4700::/32"));
+    assert_eq!(flow.observation_domain_id, Some(256));
+    assert_eq!(flow.src_mac, None);
+    assert_eq!(flow.as_path, None);
+}
+
+#[test]
+fn parses_ipfix() {
+    let flow = parse("ipfix.json");
+
+    assert_eq!(flow.flow_type, "IPFIX");
+    assert_eq!(flow.time_received_ns, 1755046800000000000);
+    assert_eq!(flow.sequence_num, 96);
+    assert_eq!(flow.sampling_rate, 1);
+    assert_eq!(flow.sampler_address, "198.51.100.1");
+    assert_eq!(flow.time_flow_start_ns, 1755046799000000000);
+    assert_eq!(flow.time_flow_end_ns, 1755046799500000000);
+    assert_eq!(flow.bytes, 15230);
+    assert_eq!(flow.packets, 12);
+    assert_eq!(flow.src_addr, "172.16.5.4");
+    assert_eq!(flow.dst_addr, "203.0.113.50");
+    assert_eq!(flow.etype, "IPv4");
+    assert_eq!(flow.proto, "ICMP");
+    assert_eq!(flow.src_port, 0);
+    assert_eq!(flow.dst_port, 0);
+    assert_eq!(flow.in_if, 4);
+    assert_eq!(flow.out_if, 5);
+    assert_eq!(flow.src_mac.as_deref(), Some("aa:bb:cc:00:11:22"));
+    assert_eq!(flow.dst_mac.as_deref(), Some("de:ad:be:ef:00:01"));
+    assert_eq!(flow.src_vlan, Some(100));
+    assert_eq!(flow.dst_vlan, Some(200));
+    assert_eq!(flow.vlan_id, Some(100));
+    assert_eq!(flow.ip_tos, Some(136));
+    assert_eq!(flow.forwarding_status, Some(64));
+    assert_eq!(flow.ip_ttl, Some(255));
+    assert_eq!(flow.ip_flags, Some(0));
+    assert_eq!(flow.tcp_flags, Some(0));
+    assert_eq!(flow.icmp_type, Some(8));
+    assert_eq!(flow.icmp_code, Some(0));
+    assert_eq!(flow.ipv6_flow_label, None);
+    assert_eq!(flow.fragment_id, Some(4242));
+    assert_eq!(flow.fragment_offset, Some(0));
+    assert_eq!(flow.src_as, Some(64514));
+    assert_eq!(flow.dst_as, Some(64496));
+    assert_eq!(flow.next_hop.as_deref(), Some("198.51.100.254"));
+    assert_eq!(flow.next_hop_as, Some(64500));
+    assert_eq!(flow.src_net.as_deref(), Some("172.16.0.0/12"));
+    assert_eq!(flow.dst_net.as_deref(), Some("203.0.113.0/24"));
+    assert_eq!(flow.bgp_next_hop.as_deref(), Some("198.51.100.253"));
+    assert_eq!(
+        flow.bgp_communities,
+        Some(vec!["64514:100".to_string(), "64514:200".to_string()])
+    );
+    assert_eq!(flow.as_path, Some(vec![64514, 3356, 64496]));
+    assert_eq!(flow.mpls_ttl, Some(vec![64, 63]));
+    assert_eq!(flow.mpls_label, Some(vec![16001, 24005]));
+    assert_eq!(
+        flow.mpls_ip,
+        Some(vec!["10.255.0.1".to_string(), "10.255.0.2".to_string()])
+    );
+    assert_eq!(flow.observation_domain_id, Some(1));
+    assert_eq!(flow.observation_point_id, Some(7));
+}
+
+#[test]
+fn rejects_malformed_records() {
+    let record = fixture("malformed.json");
+    let error = serde_json::from_str::<FlowData>(record.trim_end()).unwrap_err();
+    assert!(error.to_string().contains("invalid type"), "{}", error);
+}
+
+#[test]
+fn defaults_missing_scalar_fields() {
+    let record = br#"{"type":"NETFLOW_V9","sampler_address":"192.0.2.30","packets":3,"src_addr":"10.0.0.1","dst_addr":"8.8.8.8","etype":"IPv4","proto":"UDP"}"#;
+    let flow = GoFlow2Influxdb::decode_json(record).unwrap();
+
+    assert_eq!(flow.time_received_ns, 0);
+    assert_eq!(flow.bytes, 0);
+    assert_eq!(flow.packets, 3);
+    assert_eq!(flow.in_if, 0);
+    assert_eq!(
+        flow.defaulted,
+        [
+            "time_received_ns",
+            "sequence_num",
+            "sampling_rate",
+            "time_flow_start_ns",
+            "time_flow_end_ns",
+            "bytes",
+            "src_port",
+            "dst_port",
+            "in_if",
+            "out_if",
+        ]
+    );
+    assert!(parse("ipfix.json").defaulted.is_empty());
+}
@@ -0,0 +1,57 @@
+//! Reading records from input files as written on other platforms.
+
+use GoFlow2Influxdb::{Config, decode_json, input::Input};
+
+const RECORD: &str = r#"{"type":"IPFIX","time_received_ns":1,"sequence_num":1,"sampling_rate":1,"sampler_address":"192.0.2.1","time_flow_start_ns":1,"time_flow_end_ns":2,"bytes":10,"packets":1,"src_addr":"10.0.0.1","dst_addr":"198.51.100.20","etype":"IPv4","proto":"TCP","src_port":1234,"dst_port":443,"in_if":1,"out_if":2}"#;
+
+/// Reads every record of `contents`, written to a file read as `format`.
+async fn read(name: &str, contents: &str, format: &str) -> Vec<Vec<u8>> {
+    let path =
+        std::env::temp_dir().join(format!("goflow2influxdb-{}-{}", std::process::id(), name));
+    std::fs::write(&path, contents).unwrap();
+
+    let config = Config::from_lookup(|key| match key {
+        "INFLUXDB_URL" => Some("http://localhost:8086".to_string()),
+        "INFLUXDB_TOKEN" | "INFLUXDB_ORG" | "INFLUXDB_BUCKET" => Some("test".to_string()),
+        "GOFLOW2_INPUT_FILE" => Some(path.display().to_string()),
+        "INPUT_FORMAT" => Some(format.to_string()),
+        _ => None,
+    })
+    .unwrap();
+    let mut input = Input::open(&config).await.unwrap();
+    let mut records = Vec::new();
+    while let Some(record) = input.next_record().await.unwrap() {
+        records.push(record);
+    }
+
+    std::fs::remove_file(&path).unwrap();
+    records
+}
+
+#[tokio::test]
+async fn strips_crlf_line_endings() {
+    let records = read(
+        "crlf.json",
+        &format!("{}\r\n{}\r\r\n\r\n", RECORD, RECORD),
+        "json",
+    )
+    .await;
+
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0], RECORD.as_bytes());
+    assert_eq!(records[1], RECORD.as_bytes());
+    assert!(records[2].is_empty());
+    assert_eq!(decode_json(&records[1]).unwrap().bytes, 10);
+}
+
+#[tokio::test]
+async fn strips_crlf_line_endings_from_csv_rows() {
+    let csv =
+        "type,src_addr,dst_addr,dst_mac\r\nIPFIX,10.0.0.1,198.51.100.20,00:11:22:33:44:55\r\n";
+    let records = read("crlf.csv", csv, "csv").await;
+
+    assert_eq!(
+        records,
+        [b"IPFIX,10.0.0.1,198.51.100.20,00:11:22:33:44:55".to_vec()]
+    );
+}